@@ -0,0 +1,15 @@
+//! Fuzzes reading csv lines without a header into `Accounts`, like the server does, see
+//! `csv_accounts::check_csv_lines`. Run with
+//! `cargo fuzz run csv_lines fuzz/corpus/csv_lines fuzz/seeds/csv_lines` from the root of the
+//! repository, to start from the checked in seeds.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// the check of the other csv target is unused
+#[allow(dead_code)]
+#[path = "../../tests/support/csv_accounts.rs"]
+mod csv_accounts;
+
+fuzz_target!(|data: &[u8]| csv_accounts::check_csv_lines(data));
@@ -0,0 +1,14 @@
+//! Fuzzes reading a csv file into `Accounts`, see `csv_accounts::check_csv_transactions`. Run
+//! with `cargo fuzz run csv_transactions fuzz/corpus/csv_transactions fuzz/seeds/csv_transactions`
+//! from the root of the repository, to start from the checked in seeds.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// the check of the other csv target is unused
+#[allow(dead_code)]
+#[path = "../../tests/support/csv_accounts.rs"]
+mod csv_accounts;
+
+fuzz_target!(|data: &[u8]| csv_accounts::check_csv_transactions(data));
@@ -0,0 +1,11 @@
+//! Fuzzes the sharded runtimes against applying the same transactions sequentially, see
+//! `differential::check`. Run with `cargo fuzz run differential` from the root of the repository.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/support/differential.rs"]
+mod differential;
+
+fuzz_target!(|scenario: differential::Scenario| differential::check(&scenario));
@@ -1,5 +1,6 @@
 use fnv::FnvHashMap;
 use std::collections::hash_map;
+use std::fmt::{Display, Formatter};
 
 pub type TxId = u32;
 pub type ClientId = u16;
@@ -8,7 +9,7 @@ pub type ClientId = u16;
 // representing these internally as integers, depending on the workload. They are kept as floats here
 // for code clarity.
 pub type Amount = f32;
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransactionError {
     AccountLocked,
     InsufficientFunds,
@@ -19,13 +20,47 @@ pub enum TransactionError {
     InvalidAmount,
 }
 
+impl TransactionError {
+    pub const ALL: [TransactionError; 7] = [
+        TransactionError::AccountLocked,
+        TransactionError::InsufficientFunds,
+        TransactionError::NotDisputed,
+        TransactionError::AlreadyDisputed,
+        TransactionError::TransactionNotFound,
+        TransactionError::DuplicateTransaction,
+        TransactionError::InvalidAmount,
+    ];
+
+    /// A stable, machine readable name of the error, as used in reports
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::AccountLocked => "account_locked",
+            TransactionError::InsufficientFunds => "insufficient_funds",
+            TransactionError::NotDisputed => "not_disputed",
+            TransactionError::AlreadyDisputed => "already_disputed",
+            TransactionError::TransactionNotFound => "transaction_not_found",
+            TransactionError::DuplicateTransaction => "duplicate_transaction",
+            TransactionError::InvalidAmount => "invalid_amount",
+        }
+    }
+}
+
+impl Display for TransactionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+#[derive(Clone, Debug, PartialEq)]
 struct Deposit {
     amount: Amount,
     disputed: bool,
 }
 
 /// Represents the account of a single client
-#[derive(Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Account {
     // Our keys are just 4 bytes, so let's use Fnv hashing to speed things up
     deposits: FnvHashMap<TxId, Deposit>,
@@ -41,11 +76,10 @@ impl Account {
     /// # Errors
     /// - `DuplicateTransaction` if a deposit with this id has already been processed
     /// - `AccountLocked` if the account is locked
+    /// - `InvalidAmount` if the amount is not positive and finite
     pub fn deposit(&mut self, tx_id: TxId, amount: Amount) -> Result<(), TransactionError> {
         self.check_not_locked()?;
-        if amount <= 0.0 {
-            return Err(TransactionError::InvalidAmount);
-        }
+        check_amount(amount)?;
         match self.deposits.entry(tx_id) {
             hash_map::Entry::Occupied(_) => Err(TransactionError::DuplicateTransaction),
             hash_map::Entry::Vacant(entry) => {
@@ -65,11 +99,10 @@ impl Account {
     /// # Errors
     /// - `InsufficientFunds` if the withdrawal puts the account into overdraft
     /// - `AccountLocked` if the account is locked
+    /// - `InvalidAmount` if the amount is not positive and finite
     pub fn withdraw(&mut self, amount: Amount) -> Result<(), TransactionError> {
         self.check_not_locked()?;
-        if amount <= 0.0 {
-            return Err(TransactionError::InvalidAmount);
-        }
+        check_amount(amount)?;
         let amount = amount as f64;
         if self.available() < amount {
             Err(TransactionError::InsufficientFunds)
@@ -185,6 +218,17 @@ impl Account {
     }
 }
 
+/// Return `Err(TransactionError::InvalidAmount)` unless `amount` is positive and finite. The csv
+/// format accepts amounts like `NaN` and `inf`, which would make the balances meaningless.
+#[inline]
+fn check_amount(amount: Amount) -> Result<(), TransactionError> {
+    if amount > 0.0 && amount.is_finite() {
+        Ok(())
+    } else {
+        Err(TransactionError::InvalidAmount)
+    }
+}
+
 /// A collection of accounts, indexed by client id
 #[derive(Default)]
 pub struct Accounts {
@@ -224,6 +268,34 @@ impl Accounts {
     pub fn chargeback(&mut self, client_id: ClientId, tx_id: TxId) -> Result<(), TransactionError> {
         self.client_account(client_id).chargeback(tx_id)
     }
+
+    /// The account of a client, if it has one, without creating it.
+    pub fn get(&self, client_id: ClientId) -> Option<&Account> {
+        self.accounts.get(&client_id)
+    }
+
+    /// Remove the account of a client, e.g. to move it to another `Accounts`.
+    pub fn remove(&mut self, client_id: ClientId) -> Option<Account> {
+        self.accounts.remove(&client_id)
+    }
+
+    /// Insert the account of a client, replacing its current account.
+    pub fn insert(&mut self, client_id: ClientId, account: Account) {
+        self.accounts.insert(client_id, account);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, &Account)> {
+        self.accounts
+            .iter()
+            .map(|(client_id, account)| (*client_id, account))
+    }
+
+    /// Consume the accounts and return them ordered by client id.
+    pub fn into_sorted_vec(self) -> Vec<(ClientId, Account)> {
+        let mut accounts = self.accounts.into_iter().collect::<Vec<_>>();
+        accounts.sort_unstable_by_key(|(client_id, _)| *client_id);
+        accounts
+    }
 }
 
 impl IntoIterator for Accounts {
@@ -238,6 +310,8 @@ impl IntoIterator for Accounts {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
 
     fn assert_balances(account: &Account, available: f64, held: f64, total: f64) {
         assert_eq!(account.available(), available);
@@ -336,4 +410,103 @@ mod tests {
         assert!(account.withdraw(99.0).is_ok());
         assert_balances(&account, 1.0, 0.0, 1.0);
     }
+
+    /// An operation on an `Account`, generated by `operation`
+    #[derive(Clone, Debug)]
+    enum Operation {
+        Deposit(TxId, Amount),
+        Withdraw(Amount),
+        Dispute(TxId),
+        Resolve(TxId),
+        Chargeback(TxId),
+    }
+
+    impl Operation {
+        fn apply(&self, account: &mut Account) -> Result<(), TransactionError> {
+            match *self {
+                Operation::Deposit(tx_id, amount) => account.deposit(tx_id, amount),
+                Operation::Withdraw(amount) => account.withdraw(amount),
+                Operation::Dispute(tx_id) => account.dispute(tx_id),
+                Operation::Resolve(tx_id) => account.resolve(tx_id),
+                Operation::Chargeback(tx_id) => account.chargeback(tx_id),
+            }
+        }
+    }
+
+    /// Operations on a few transaction ids, so disputes mostly refer to earlier deposits, with
+    /// amounts of up to four decimals, including zero, negative and non-finite ones.
+    fn operation() -> impl Strategy<Value = Operation> {
+        let tx_id = 0..8 as TxId;
+        let amount = prop_oneof![
+            20 => (-10_000..100_000_000).prop_map(|amount: i32| amount as Amount / 10_000.0),
+            1 => prop::sample::select(vec![Amount::NAN, Amount::INFINITY, Amount::NEG_INFINITY]),
+        ];
+        prop_oneof![
+            3 => (tx_id.clone(), amount.clone())
+                .prop_map(|(tx_id, amount)| Operation::Deposit(tx_id, amount)),
+            2 => amount.prop_map(Operation::Withdraw),
+            2 => tx_id.clone().prop_map(Operation::Dispute),
+            1 => tx_id.clone().prop_map(Operation::Resolve),
+            1 => tx_id.prop_map(Operation::Chargeback),
+        ]
+    }
+
+    proptest! {
+        /// Check the invariants of an account after every operation. A failing sequence is shrunk
+        /// to a minimal one by proptest.
+        #[test]
+        fn test_account_invariants(operations in vec(operation(), 0..64)) {
+            let mut account = Account::default();
+            for operation in operations {
+                let before = account.clone();
+                let result = operation.apply(&mut account);
+                // the funds are sums of f32 amounts in f64. An amount like 0.0001 has bits down to
+                // 2^-37, so once the funds reach 2^16 they no longer fit the 53 bits of f64, and
+                // may round in the last bit
+                let drift = account.available() + account.held() - account.total();
+                prop_assert!(drift.abs() <= account.total().abs() * 1e-12);
+                prop_assert!(account.held() >= 0.0);
+                prop_assert!(account.total().is_finite());
+                if result.is_err() {
+                    prop_assert_eq!(&account, &before, "{:?} changed the account", operation);
+                }
+                if let (Operation::Chargeback(_), Ok(())) = (&operation, result) {
+                    prop_assert!(account.is_locked());
+                }
+                // nothing unlocks an account
+                prop_assert!(account.is_locked() || !before.is_locked());
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_finite_amount() {
+        let mut account = Account::default();
+        for amount in [Amount::NAN, Amount::INFINITY, Amount::NEG_INFINITY] {
+            assert_eq!(
+                account.deposit(1, amount),
+                Err(TransactionError::InvalidAmount)
+            );
+            assert_eq!(
+                account.withdraw(amount),
+                Err(TransactionError::InvalidAmount)
+            );
+        }
+        assert!(account.deposit(1, Amount::MAX).is_ok());
+        assert_balances(&account, Amount::MAX as f64, 0.0, Amount::MAX as f64);
+    }
+
+    #[test]
+    fn test_into_sorted_vec() {
+        let mut accounts = Accounts::default();
+        for client_id in [5, 1, 300, 2] {
+            accounts.deposit(client_id, client_id as TxId, 1.0).unwrap();
+        }
+        let client_ids = accounts
+            .into_sorted_vec()
+            .into_iter()
+            .map(|(client_id, _)| client_id)
+            .collect::<Vec<_>>();
+        assert_eq!(client_ids, [1, 2, 5, 300]);
+    }
 }
@@ -0,0 +1,279 @@
+use crate::account::ClientId;
+use crate::io::Format;
+use crate::io::compression::Compression;
+use crate::rt::affinity::CoreList;
+use clap::builder::RangedU64ValueParser;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+/// The process completed successfully
+pub const EXIT_SUCCESS: u8 = 0;
+/// A check failed: `validate` found problems, or `reconcile` found differences
+pub const EXIT_CHECK_FAILED: u8 = 1;
+/// Processing failed, e.g. because an input could not be read or parsed, or the output could not
+/// be written
+pub const EXIT_FAILURE: u8 = 3;
+/// Processing completed, but some transactions were skipped by `--max-bad-rows` or quarantined
+/// because processing them panicked, or `process --partial` left out the accounts of failed shards
+pub const EXIT_INCOMPLETE: u8 = 4;
+
+/// Toy payments engine. Reads transactions and writes the resulting client accounts. Without a
+/// command, the arguments are those of the process command.
+#[derive(Parser)]
+#[command(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    after_help = "Exit codes:\n  \
+        0  success\n  \
+        1  check failed: validate found problems, or reconcile found differences\n  \
+        2  invalid command line\n  \
+        3  processing failed, e.g. an input could not be read or parsed\n  \
+        4  incomplete: some transactions were skipped, or transactions or shards failed, see the \
+        messages on stderr"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    process: ProcessArgs,
+}
+
+impl Cli {
+    /// The command to run, which is `process` if none is given, like `ktht transactions.csv`.
+    pub fn into_command(self) -> Command {
+        self.command.unwrap_or(Command::Process(self.process))
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Process transactions and write the resulting accounts
+    Process(ProcessArgs),
+    /// Check transactions for problems, like unknown types or disputes of unknown transactions,
+    /// without writing balances
+    Validate(ValidateArgs),
+    /// Print statistics about transactions, without processing them
+    Stats(StatsArgs),
+    /// Apply transactions one by one on a single thread and write the outcome of every transaction
+    /// with the resulting state of its account
+    Replay(ReplayArgs),
+    /// Process transactions and compare the resulting accounts to the expected accounts
+    Reconcile(ReconcileArgs),
+    /// Convert transactions to the binary format
+    Convert(ConvertArgs),
+    /// Serve clients streaming transaction lines over a socket, applying them as they arrive
+    Serve(ServeArgs),
+}
+
+/// Options for reading transactions
+#[derive(Args)]
+pub struct InputArgs {
+    /// Paths of the files containing the transactions, or - for stdin. A directory stands for the
+    /// files it contains, ordered by file name. The inputs are processed as if concatenated in the
+    /// order given, so transactions of a client are applied in input order, then line order.
+    /// Gzip and zstd compressed inputs are decompressed transparently.
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+    /// Format of all inputs, one of csv, jsonl or binary [default: guessed from the file
+    /// extension of every input, or csv]
+    #[arg(long)]
+    pub input_format: Option<Format>,
+}
+
+/// Options for writing the output of a command
+#[derive(Args)]
+pub struct OutputArgs {
+    /// Write the output to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Compression of the output, one of none, gzip or zstd [default: guessed from the file
+    /// extension, or none]
+    #[arg(long)]
+    pub compress: Option<Compression>,
+}
+
+/// Options for the sharded runtime
+#[derive(Args)]
+pub struct RuntimeArgs {
+    /// Number of worker threads processing transactions [default: number of cores]
+    #[arg(short = 'j', long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub threads: Option<usize>,
+    /// TOML file assigning clients to shards, with a `strategy` of modulo or hash, and a `[pin]`
+    /// table of `client = shard` pinning heavy clients to dedicated shards [default: client
+    /// modulo the number of shards]
+    #[arg(long, value_name = "FILE")]
+    pub shard_config: Option<PathBuf>,
+    /// Cores to run the worker threads on, in order, like 2-15 or 0,2,4-7 [default: all cores,
+    /// except the reader core]
+    #[arg(long, value_name = "LIST")]
+    pub cores: Option<CoreList>,
+    /// Let the operating system schedule the worker threads instead of pinning each to a core
+    #[arg(long)]
+    pub no_pin: bool,
+    /// Pin the thread reading the transactions to this core, keeping it apart from the workers. It
+    /// can't be one of --cores
+    #[arg(long, value_name = "CORE")]
+    pub reader_core: Option<usize>,
+    /// Print which cores the worker threads and the reader run on to stderr
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+#[derive(Args)]
+pub struct ProcessArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Format of the output, one of csv or jsonl [default: guessed from the file extension, or csv]
+    #[arg(long)]
+    pub output_format: Option<Format>,
+    /// Write accounts ordered by client id, making the output independent of the number of cores
+    #[arg(long)]
+    pub sort: bool,
+    /// Write a csv report of rejected transactions to this file, or - for stderr
+    #[arg(long)]
+    pub errors: Option<PathBuf>,
+    /// Print the metrics of every shard to stderr at the end of the run, like the number of
+    /// transactions it processed, how busy it was and its NUMA node
+    #[arg(long)]
+    pub stats: bool,
+    /// Move clients from the busiest shard to the least busy one while processing, when their
+    /// queues are imbalanced
+    #[arg(long)]
+    pub rebalance: bool,
+    /// Run all shards on the main thread, folding one transaction at a time in input order, so a
+    /// run is reproduced exactly and can be stepped through in a debugger. The transactions are
+    /// routed to as many shards as there would be threads.
+    #[arg(long, conflicts_with = "rebalance")]
+    pub deterministic: bool,
+    /// If a shard fails, still write the accounts of the other shards instead of failing the run
+    #[arg(long)]
+    pub partial: bool,
+    /// Stop reading at the first rejected transaction and fail the run, after writing it to the
+    /// `--errors` report
+    #[arg(long)]
+    pub fail_fast: bool,
+    /// Skip up to this many transactions that can't be read, printing a warning with the position
+    /// of each, instead of failing the run at the first one. An input that can't be opened,
+    /// decompressed or read to its end still fails the run
+    #[arg(long, default_value_t = 0)]
+    pub max_bad_rows: u64,
+    #[command(flatten)]
+    pub runtime: RuntimeArgs,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    #[command(flatten)]
+    pub runtime: RuntimeArgs,
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Only write the transactions of this client, may be given several times
+    #[arg(long = "client")]
+    pub clients: Vec<ClientId>,
+}
+
+#[derive(Args)]
+pub struct ReconcileArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// File containing the expected accounts, as written by the process command
+    #[arg(long)]
+    pub expected: PathBuf,
+    /// Format of the expected accounts, one of csv or jsonl [default: guessed from the file
+    /// extension, or csv]
+    #[arg(long)]
+    pub expected_format: Option<Format>,
+    /// Run all shards on the main thread, folding one transaction at a time in input order, so a
+    /// run is reproduced exactly and can be stepped through in a debugger. The transactions are
+    /// routed to as many shards as there would be threads.
+    #[arg(long)]
+    pub deterministic: bool,
+    /// Skip up to this many transactions that can't be read, printing a warning with the position
+    /// of each, instead of failing the run at the first one. An input that can't be opened,
+    /// decompressed or read to its end still fails the run
+    #[arg(long, default_value_t = 0)]
+    pub max_bad_rows: u64,
+    #[command(flatten)]
+    pub runtime: RuntimeArgs,
+}
+
+#[derive(Args)]
+pub struct ConvertArgs {
+    /// Path of the file containing the transactions, or - for stdin
+    pub input: PathBuf,
+    /// Path of the binary file to write
+    pub output: PathBuf,
+    /// Format of the input, one of csv or jsonl [default: guessed from the file extension, or csv]
+    #[arg(long)]
+    pub input_format: Option<Format>,
+    /// Compression of the output, one of none, gzip or zstd [default: guessed from the file
+    /// extension, or none]
+    #[arg(long)]
+    pub compress: Option<Compression>,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    #[command(flatten)]
+    pub listen: ListenArgs,
+    /// Address to serve Prometheus metrics on, like 127.0.0.1:9100. They are served on /metrics.
+    #[arg(long)]
+    pub metrics: Option<String>,
+    /// Move clients from the busiest shard to the least busy one, when their queues are imbalanced
+    #[arg(long)]
+    pub rebalance: bool,
+    #[command(flatten)]
+    pub runtime: RuntimeArgs,
+}
+
+/// Addresses to accept connections on, at least one of which must be given. All of them are
+/// served by the same runtime, so they share the accounts.
+#[derive(Args)]
+#[group(required = true, multiple = true)]
+pub struct ListenArgs {
+    /// Address to accept TCP connections on, like 127.0.0.1:7878
+    #[arg(long)]
+    pub listen: Option<String>,
+    /// Path of a Unix socket to accept connections on
+    #[arg(long)]
+    pub unix: Option<PathBuf>,
+    /// Address to serve the HTTP/JSON API on, like 127.0.0.1:8080
+    #[cfg(feature = "http")]
+    #[arg(long)]
+    pub http: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+}
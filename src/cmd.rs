@@ -0,0 +1,408 @@
+use crate::account::{Account, Accounts, ClientId};
+use crate::cli::{InputArgs, OutputArgs, RuntimeArgs};
+use crate::io::compression::{CompressedWriter, Compression};
+use crate::io::input::{Input, MultiInputReader};
+use crate::io::{AccountWriter, CsvTransaction, ReadError};
+use crate::rt;
+use crate::rt::RuntimeError;
+use crate::rt::affinity::Placement;
+use crate::rt::assign::{AssignmentConfig, ShardAssigner};
+use crate::rt::deterministic::DeterministicRuntime;
+use crate::rt::fallible::OnError;
+use crate::rt::metrics::{Histogram, ShardStats};
+use crate::rt::numa::Topology;
+use crate::rt::outcome::{Outcome, Quarantined};
+use crate::rt::rebalance::{Migrate, RebalancePolicy};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{BufWriter, Write, stderr, stdout};
+use std::path::Path;
+
+pub mod convert;
+pub mod process;
+pub mod reconcile;
+pub mod replay;
+pub mod serve;
+pub mod stats;
+pub mod validate;
+
+/// The outcome of a command that completed without errors
+pub enum Status {
+    Success,
+    /// The command completed, but the check it performs failed
+    CheckFailed,
+    /// The command completed, but some transactions were skipped, or transactions or shards
+    /// failed, see `skip_bad_rows` and `report_incidents`
+    Incomplete,
+}
+
+pub type Error = Box<dyn std::error::Error>;
+
+pub type Result = std::result::Result<Status, Error>;
+
+/// Create a reader for all transactions of the inputs given on the command line.
+pub fn transaction_reader(args: InputArgs) -> std::io::Result<MultiInputReader> {
+    Ok(MultiInputReader::new(
+        Input::expand(args.inputs)?,
+        args.input_format,
+    ))
+}
+
+/// Open the output given on the command line, or stdout if no path is given. Unless a compression
+/// is given, the output is compressed if the path has a compression extension, like
+/// `accounts.csv.gz`.
+pub fn open_output(args: &OutputArgs) -> std::io::Result<CompressedWriter<Box<dyn Write>>> {
+    let path = args.output.as_deref();
+    let compression = args
+        .compress
+        .or_else(|| path.and_then(Compression::from_path))
+        .unwrap_or(Compression::None);
+    let output: Box<dyn Write> = match path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(stdout()),
+    };
+    CompressedWriter::new(compression, output)
+}
+
+/// Open a report file given on the command line, where `-` means stderr.
+pub fn open_report(path: &Path) -> std::io::Result<Box<dyn Write>> {
+    if path.as_os_str() == "-" {
+        Ok(Box::new(stderr()))
+    } else {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+/// The placement of the worker threads given on the command line, with as many threads as cores
+/// by default. The runtime starts at most one thread per core, so a warning is printed if more are
+/// requested. The `max_threads` of the placement is the number of shards the runtime starts.
+///
+/// # Errors
+/// If the cores can't be used, or the reader core is one of the cores of the worker threads, which
+/// would defeat keeping the reader apart.
+pub fn placement(args: &RuntimeArgs) -> std::result::Result<Placement, Error> {
+    if let (Some(core), Some(cores)) = (args.reader_core, &args.cores)
+        && cores.ids().contains(&core)
+    {
+        return Err(format!("--reader-core {core} is also one of --cores").into());
+    }
+    // The number of threads used by the system is the number of cores + 1, but since the main
+    // thread is mostly IO-bound, this should be ok. It can be kept off the cores of the shards
+    // with `--reader-core`.
+    let mut placement = Placement {
+        max_threads: usize::MAX,
+        cores: args.cores.clone(),
+        pin: !args.no_pin,
+        reader_core: args.reader_core,
+        topology: Topology::discover(),
+    };
+    let cores = placement.shard_cores()?.ids().len();
+    placement.max_threads = match args.threads {
+        Some(threads) if threads > cores => {
+            eprintln!(
+                "warning: {threads} threads requested, but only {cores} cores are available, \
+                using {cores} threads"
+            );
+            cores
+        }
+        Some(threads) => threads,
+        None => cores,
+    };
+    Ok(placement)
+}
+
+/// Print which cores the shards and the reader run on to stderr, before starting a runtime with
+/// `placement`, or that they all run on the main thread if the run is `deterministic`. This is only
+/// printed with `--verbose`, so the output of a run is limited to its warnings and incidents.
+pub fn report_placement(
+    placement: &Placement,
+    deterministic: bool,
+) -> std::result::Result<(), RuntimeError> {
+    let shards = placement.max_threads;
+    if deterministic {
+        eprintln!("running {shards} shards on the main thread, in input order");
+        return Ok(());
+    }
+    let cores = placement.shard_cores()?;
+    if placement.pin {
+        eprint!("running {shards} shards pinned to cores {cores}, in shard order");
+    } else {
+        eprint!("running {shards} shards without pinning");
+    }
+    match placement.reader_core {
+        Some(core) => eprintln!(", reader pinned to core {core}"),
+        None => eprintln!(),
+    }
+    let topology = &placement.topology;
+    if placement.pin && topology.num_nodes() > 1 {
+        let mut per_node = BTreeMap::<_, Vec<_>>::new();
+        for (shard, &core) in cores.ids().iter().enumerate() {
+            per_node
+                .entry(topology.node_of(core))
+                .or_default()
+                .push(shard.to_string());
+        }
+        for (node, shards) in per_node {
+            let node = node.map_or("unknown".to_string(), |node| node.to_string());
+            eprintln!("NUMA node {node}: shards {}", shards.join(","));
+        }
+    }
+    Ok(())
+}
+
+/// The assignment of clients to `threads` shards, loaded from the config file given on the command
+/// line, or `key % threads` without one.
+pub fn shard_assigner(
+    args: &RuntimeArgs,
+    threads: usize,
+) -> std::result::Result<Box<dyn ShardAssigner>, Error> {
+    let Some(path) = &args.shard_config else {
+        return Ok(Box::new(rt::assign::Modulo));
+    };
+    let config = std::fs::read_to_string(path)
+        .map_err(|e| format!("{}: {e}", path.display()))
+        .and_then(|config| {
+            AssignmentConfig::from_toml(&config).map_err(|e| format!("{}: {e}", path.display()))
+        })?;
+    if let Some((client, shard)) = config.pin.iter().find(|(_, shard)| **shard >= threads) {
+        return Err(format!(
+            "{}: client {client} is pinned to shard {shard}, but there are only {threads} shards",
+            path.display()
+        )
+        .into());
+    }
+    Ok(config.assigner())
+}
+
+/// Where `fold_transactions` runs the shards
+#[derive(Clone, Copy)]
+pub enum Schedule<'a> {
+    /// On a `ShardedThreadPerCoreRuntime`, moving clients between shards if a `rebalance` policy
+    /// is given
+    Threaded(Option<&'a RebalancePolicy>),
+    /// On the calling thread, see `rt::deterministic`. The queues of the shards never build up,
+    /// so there is nothing to rebalance.
+    Deterministic,
+}
+
+impl<'a> Schedule<'a> {
+    pub fn new(deterministic: bool, rebalance: Option<&'a RebalancePolicy>) -> Self {
+        if deterministic {
+            Schedule::Deterministic
+        } else {
+            Schedule::Threaded(rebalance)
+        }
+    }
+}
+
+/// Process all transactions on the shards of `placement` as given by `schedule`, and return the
+/// result of `finish` on the final state of every shard, computed on the shard, and their metrics
+/// along with the quarantined transactions.
+pub fn fold_transactions<S: Default + Migrate + Send + 'static, R: Send + 'static>(
+    placement: Placement,
+    assigner: Box<dyn ShardAssigner>,
+    schedule: Schedule,
+    func: fn(&mut S, CsvTransaction),
+    finish: fn(S) -> R,
+    tx_reader: impl Iterator<Item = std::result::Result<CsvTransaction, ReadError>>,
+) -> std::result::Result<Outcome<R, CsvTransaction>, Error> {
+    let func = move |state: &mut S, tx| {
+        func(state, tx);
+        Ok::<_, Infallible>(())
+    };
+    fold_transactions_fallible(
+        placement,
+        assigner,
+        schedule,
+        func,
+        finish,
+        tx_reader,
+        |e| match e {},
+    )
+}
+
+/// Like `fold_transactions`, but `func` may reject transactions, which are handed to `on_error`
+/// while processing, see `ShardedThreadPerCoreRuntime::try_fold_fallible`.
+pub fn fold_transactions_fallible<S, R, E>(
+    placement: Placement,
+    assigner: Box<dyn ShardAssigner>,
+    schedule: Schedule,
+    func: impl Fn(&mut S, CsvTransaction) -> std::result::Result<(), E> + Clone + Send + 'static,
+    finish: fn(S) -> R,
+    tx_reader: impl Iterator<Item = std::result::Result<CsvTransaction, ReadError>>,
+    on_error: impl FnMut(E) -> OnError,
+) -> std::result::Result<Outcome<R, CsvTransaction>, Error>
+where
+    S: Default + Migrate + Send + 'static,
+    R: Send + 'static,
+    E: Send + 'static,
+{
+    let tx_reader = tx_reader.map(|tx| tx.map_err(Error::from));
+    match schedule {
+        Schedule::Deterministic => DeterministicRuntime::try_fold_fallible(
+            placement, assigner, func, finish, tx_reader, on_error,
+        ),
+        Schedule::Threaded(rebalance) => rt::ShardedThreadPerCoreRuntime::try_fold_rebalanced(
+            placement, assigner, rebalance, func, finish, tx_reader, on_error,
+        ),
+    }
+}
+
+/// Skip up to `max_bad_rows` transactions that can't be read, printing a warning with the position
+/// of every skipped row, and count them in `skipped`. Only errors confined to a row are skipped, see
+/// `ReadError::is_record_error`; an input that can't be read still fails the run.
+pub fn skip_bad_rows<'a>(
+    tx_reader: impl Iterator<Item = std::result::Result<CsvTransaction, ReadError>> + 'a,
+    max_bad_rows: u64,
+    skipped: &'a mut u64,
+) -> impl Iterator<Item = std::result::Result<CsvTransaction, ReadError>> + 'a {
+    rt::fallible::skip_errors(tx_reader, max_bad_rows, ReadError::is_record_error, |e| {
+        eprintln!("warning: skipped a bad row: {e}");
+        *skipped += 1;
+    })
+}
+
+/// Write the quarantined transactions and the failed shards of a run to stderr, and return
+/// whether there were any.
+pub fn report_incidents<S>(outcome: &Outcome<S, CsvTransaction>) -> bool {
+    for Quarantined {
+        shard,
+        item,
+        message,
+    } in &outcome.quarantined
+    {
+        eprintln!("quarantined transaction {item} on shard {shard}: {message}");
+    }
+    let mut failed = false;
+    for failure in outcome.failures() {
+        eprintln!("error: {failure}");
+        failed = true;
+    }
+    failed || !outcome.quarantined.is_empty()
+}
+
+/// Write a table of the metrics of every shard, followed by how much busier the busiest shard was
+/// than the average, to spot skew in the routing of clients to shards.
+pub fn write_runtime_stats(writer: &mut impl Write, stats: &[ShardStats]) -> std::io::Result<()> {
+    let total_items = stats.iter().map(|stats| stats.items).sum::<u64>();
+    writeln!(
+        writer,
+        "{:>5} {:>4} {:>12} {:>7} {:>10} {:>10} {:>10} {:>6} {:>10} {:>10} {:>10}",
+        "shard", "node", "items", "share", "queue max", "busy", "idle", "util", "p50", "p99", "max"
+    )?;
+    let mut latency = Histogram::default();
+    for (shard, stats) in stats.iter().enumerate() {
+        write_stats_row(writer, &shard.to_string(), stats, total_items)?;
+        latency.merge(&stats.latency);
+    }
+    let all = ShardStats {
+        items: total_items,
+        queue_high_water_mark: stats
+            .iter()
+            .map(|s| s.queue_high_water_mark)
+            .max()
+            .unwrap_or(0),
+        busy: stats.iter().map(|stats| stats.busy).sum(),
+        idle: stats.iter().map(|stats| stats.idle).sum(),
+        latency,
+        ..ShardStats::default()
+    };
+    write_stats_row(writer, "all", &all, total_items)?;
+    let remote = stats.iter().filter_map(|stats| stats.remote_messages);
+    let nodes = stats.iter().filter_map(|stats| stats.node);
+    if stats.iter().any(|stats| stats.remote_messages.is_some()) {
+        writeln!(
+            writer,
+            "cross-node: {} messages were submitted from another NUMA node than their shard's",
+            remote.sum::<u64>()
+        )?;
+    } else if nodes.collect::<BTreeSet<_>>().len() > 1 {
+        writeln!(
+            writer,
+            "cross-node: unknown, pin the reader with --reader-core to count the messages \
+            submitted from another NUMA node"
+        )?;
+    }
+    let max_items = stats.iter().map(|stats| stats.items).max().unwrap_or(0);
+    if total_items > 0 {
+        let mean_items = total_items as f64 / stats.len() as f64;
+        writeln!(
+            writer,
+            "skew: the busiest shard processed {:.2}x the mean number of items",
+            max_items as f64 / mean_items
+        )?;
+    }
+    Ok(())
+}
+
+fn write_stats_row(
+    writer: &mut impl Write,
+    shard: &str,
+    stats: &ShardStats,
+    total_items: u64,
+) -> std::io::Result<()> {
+    let share = stats.items as f64 / total_items.max(1) as f64;
+    let quantile = |q| match stats.latency.quantile(q) {
+        Some(latency) => format!("<{latency:?}"),
+        None => "-".to_string(),
+    };
+    writeln!(
+        writer,
+        "{shard:>5} {:>4} {:>12} {:>6.1}% {:>10} {:>10} {:>10} {:>5.1}% {:>10} {:>10} {:>10}",
+        stats.node.map_or("-".to_string(), |node| node.to_string()),
+        stats.items,
+        share * 100.0,
+        stats.queue_high_water_mark,
+        format!("{:.1?}", stats.busy),
+        format!("{:.1?}", stats.idle),
+        stats.utilization() * 100.0,
+        quantile(0.5),
+        quantile(0.99),
+        quantile(1.0),
+    )
+}
+
+/// Apply a `io::CsvTransaction` to an `account::Accounts` instance.
+pub fn process_transaction(accounts: &mut Accounts, tx: CsvTransaction) {
+    // We ignore all errors and continue processing to generate the end state for
+    // all accounts no matter what.
+    let _ = tx.execute_transaction(accounts);
+}
+
+/// The `finish` of `fold_transactions` collecting the accounts of a shard for `write_accounts`,
+/// ordered by client id if `sort` is set, so every shard sorts its own accounts.
+pub fn shard_accounts(sort: bool) -> fn(Accounts) -> Vec<(ClientId, Account)> {
+    if sort {
+        Accounts::into_sorted_vec
+    } else {
+        |accounts| accounts.into_iter().collect()
+    }
+}
+
+/// Write the accounts of all shards to `writer`, merging the shards in client id order if `sort`
+/// is set, in which case the accounts of every shard must be sorted, see `shard_accounts`.
+pub fn write_accounts(
+    writer: &mut impl AccountWriter,
+    shards: Vec<Vec<(ClientId, Account)>>,
+    sort: bool,
+) -> std::io::Result<()> {
+    if sort {
+        write_each(
+            writer,
+            rt::SortedMerge::new(shards.into_iter().map(Vec::into_iter)),
+        )
+    } else {
+        write_each(writer, shards.into_iter().flatten())
+    }
+}
+
+fn write_each(
+    writer: &mut impl AccountWriter,
+    accounts: impl Iterator<Item = (ClientId, Account)>,
+) -> std::io::Result<()> {
+    for (client_id, account) in accounts {
+        writer.write_account(client_id, &account)?;
+    }
+    Ok(())
+}
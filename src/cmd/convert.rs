@@ -0,0 +1,21 @@
+use super::{Status, open_output};
+use crate::cli::{ConvertArgs, OutputArgs};
+use crate::io::binary::BinaryTransactionWriter;
+use crate::io::input::{Input, MultiInputReader};
+
+/// Read transactions in any supported format and write them in the binary format.
+pub fn run(args: ConvertArgs) -> super::Result {
+    let input = Input::from_arg(args.input);
+    let tx_reader = MultiInputReader::new(vec![input], args.input_format);
+    let output = open_output(&OutputArgs {
+        output: Some(args.output),
+        compress: args.compress,
+    })?;
+    let mut tx_writer = BinaryTransactionWriter::new(output);
+    for tx in tx_reader {
+        tx_writer.write_transaction(&tx?)?;
+    }
+    tx_writer.flush()?;
+    tx_writer.into_inner().finish()?;
+    Ok(Status::Success)
+}
@@ -0,0 +1,163 @@
+use super::{
+    Schedule, Status, fold_transactions, fold_transactions_fallible, open_output, open_report,
+    placement, process_transaction, report_incidents, report_placement, shard_accounts,
+    shard_assigner, skip_bad_rows,
+};
+use crate::account::{Accounts, TransactionError};
+use crate::cli::ProcessArgs;
+use crate::io::{AccountWriter, AnyAccountWriter, CsvTransaction, Format, RejectionCsvWriter};
+use crate::rt::fallible::OnError;
+use crate::rt::outcome::{Outcome, Quarantined};
+use crate::rt::rebalance::{Migration, RebalancePolicy};
+use std::io::{Write, stderr};
+
+/// The `process` command performs the following steps:
+///
+/// 1. Initializes a transaction reader that reads the inputs one after the other in their
+///    respective formats, decompressing them if needed. Uncompressed binary files are memory mapped
+///    and decoded in place. Up to `--max-bad-rows` transactions that can't be read are skipped.
+/// 2. Sets up a multi-threaded runtime (`ShardedThreadPerCoreRuntime`), utilizing a number of threads equal to the number of CPU cores on the system
+///    unless `--threads` is given, pinned to the cores given by `--cores` unless `--no-pin` is given. The reading thread is pinned to
+///    `--reader-core` if given. On a machine with several NUMA nodes, the shards are spread over
+///    the nodes. The cores are reported on stderr with `--verbose`. Clients are assigned to shards as configured by `--shard-config`.
+///    With `--deterministic`, the shards run on the main thread instead, see `rt::deterministic`.
+/// 3. Processes transactions in parallel by using the `process_transaction` function and aggregates results.
+///    With `--rebalance`, clients are moved from the busiest shard to the least busy one along the way.
+/// 4. Writes rejected transactions to the error report as they are rejected if `--errors` is given,
+///    followed by the transactions that were quarantined because processing them panicked. With
+///    `--fail-fast`, reading stops at the first rejected transaction, which fails the run.
+/// 5. Reports quarantined transactions and failed shards on stderr. A failed shard fails the run,
+///    unless `--partial` is given, in which case the accounts of the other shards are still written,
+///    and the ids of the failed shards are listed on stderr.
+/// 6. Flattens the aggregated results and iterates over each client account, merging the shards in
+///    client id order if `--sort` is given, once every shard has sorted its own accounts.
+/// 7. Writes the processed account data to the output file or standard output using an
+///    `AccountWriter` for the output format, optionally compressed. The output is only opened
+///    once processing succeeded, so a failed run doesn't truncate the output file.
+/// 8. Writes the metrics of every shard, the messages crossing NUMA nodes and the clients moved
+///    between shards to stderr if `--stats` is given.
+pub fn run(args: ProcessArgs) -> super::Result {
+    let placement = placement(&args.runtime)?;
+    let assigner = shard_assigner(&args.runtime, placement.max_threads)?;
+    let output_format = args
+        .output_format
+        .or_else(|| args.output.output.as_deref().and_then(Format::from_path))
+        .unwrap_or(Format::Csv);
+    // fail before processing if the accounts can't be written in the output format
+    AnyAccountWriter::new(output_format, std::io::sink())?;
+    if args.runtime.verbose {
+        report_placement(&placement, args.deterministic)?;
+    }
+    let mut skipped = 0;
+    let tx_reader = skip_bad_rows(
+        super::transaction_reader(args.input)?,
+        args.max_bad_rows,
+        &mut skipped,
+    );
+    let rebalance = args.rebalance.then(RebalancePolicy::default);
+    let schedule = Schedule::new(args.deterministic, rebalance.as_ref());
+    let outcome = if args.errors.is_none() && !args.fail_fast {
+        fold_transactions(
+            placement,
+            assigner,
+            schedule,
+            process_transaction,
+            shard_accounts(args.sort),
+            tx_reader,
+        )?
+    } else {
+        let mut report = match &args.errors {
+            Some(path) => {
+                let mut report = RejectionCsvWriter::new(open_report(path)?);
+                report.write_header()?;
+                Some(report)
+            }
+            None => None,
+        };
+        let mut report_error = None;
+        let mut first_rejection = None;
+        let outcome = fold_transactions_fallible(
+            placement,
+            assigner,
+            schedule,
+            apply_transaction,
+            shard_accounts(args.sort),
+            tx_reader,
+            |(tx, error)| {
+                if let Some(report) = &mut report
+                    && let Err(e) = report.write_rejection(&tx, error)
+                {
+                    report_error.get_or_insert(e);
+                    return OnError::Abort;
+                }
+                if args.fail_fast {
+                    first_rejection.get_or_insert((tx, error));
+                    return OnError::Abort;
+                }
+                OnError::Continue
+            },
+        )?;
+        if let Some(e) = report_error {
+            return Err(e.into());
+        }
+        if let Some(mut report) = report {
+            for Quarantined { item, .. } in &outcome.quarantined {
+                report.write_rejection(item, "panicked")?;
+            }
+            report.into_inner().flush()?;
+        }
+        if let Some((tx, error)) = first_rejection {
+            return Err(format!("transaction {tx} was rejected: {error}").into());
+        }
+        outcome
+    };
+    let incomplete = report_incidents(&outcome) || skipped > 0;
+    let failed = outcome
+        .failures()
+        .map(|failure| failure.shard.to_string())
+        .collect::<Vec<_>>();
+    if args.partial && !failed.is_empty() {
+        eprintln!(
+            "incomplete: the output leaves out the accounts of failed shards {}",
+            failed.join(", ")
+        );
+    }
+    let Outcome {
+        shards,
+        stats,
+        migrations,
+        ..
+    } = outcome;
+    let shards = if args.partial {
+        shards.into_iter().flatten().collect()
+    } else {
+        shards.into_iter().collect::<Result<_, _>>()?
+    };
+    // the output is only created once the accounts are known, so a failed run leaves no output
+    let mut tx_writer = AnyAccountWriter::new(output_format, open_output(&args.output)?)?;
+    tx_writer.write_header()?;
+    super::write_accounts(&mut tx_writer, shards, args.sort)?;
+    tx_writer.flush()?;
+    tx_writer.into_inner().finish()?;
+    if args.stats {
+        super::write_runtime_stats(&mut stderr(), &stats)?;
+        for Migration { key, from, to } in migrations {
+            eprintln!("moved client {key} from shard {from} to shard {to}");
+        }
+    }
+    Ok(if incomplete {
+        Status::Incomplete
+    } else {
+        Status::Success
+    })
+}
+
+/// Apply a `CsvTransaction` to the accounts of a shard, returning the transaction if it is
+/// rejected.
+fn apply_transaction(
+    accounts: &mut Accounts,
+    tx: CsvTransaction,
+) -> Result<(), (CsvTransaction, TransactionError)> {
+    tx.execute_transaction(accounts)
+        .map_err(|error| (tx, error))
+}
@@ -0,0 +1,144 @@
+use super::{
+    Schedule, Status, fold_transactions, open_output, placement, process_transaction,
+    report_incidents, report_placement, shard_assigner, skip_bad_rows,
+};
+use crate::account::ClientId;
+use crate::cli::ReconcileArgs;
+use crate::io::compression::decompress;
+use crate::io::{AccountRecord, AccountRecordReader, Format};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::identity;
+use std::fs::File;
+use std::io::{BufReader, Write};
+
+/// Process the transactions and compare the resulting accounts to the expected accounts, as they
+/// would be written by the process command. Every difference is written as a csv row of
+/// `client,field,expected,actual`, where a missing account has the field `account`.
+pub fn run(args: ReconcileArgs) -> super::Result {
+    let expected_format = args
+        .expected_format
+        .or_else(|| Format::from_path(&args.expected))
+        .unwrap_or(Format::Csv);
+    let expected_reader = decompress(BufReader::new(File::open(&args.expected)?))?;
+    let mut expected = BTreeMap::new();
+    for record in AccountRecordReader::new(expected_format, expected_reader)? {
+        let record = record?;
+        expected.insert(record.client, record);
+    }
+
+    let placement = placement(&args.runtime)?;
+    let assigner = shard_assigner(&args.runtime, placement.max_threads)?;
+    if args.runtime.verbose {
+        report_placement(&placement, args.deterministic)?;
+    }
+    let mut skipped = 0;
+    let tx_reader = skip_bad_rows(
+        super::transaction_reader(args.input)?,
+        args.max_bad_rows,
+        &mut skipped,
+    );
+    let outcome = fold_transactions(
+        placement,
+        assigner,
+        Schedule::new(args.deterministic, None),
+        process_transaction,
+        identity,
+        tx_reader,
+    )?;
+    let incomplete = report_incidents(&outcome) || skipped > 0;
+    // differences are meaningless without the accounts of a failed shard
+    let shards = outcome.states()?;
+    let mut actual = BTreeMap::new();
+    for (client_id, account) in shards.into_iter().flatten() {
+        actual.insert(client_id, AccountRecord::new(client_id, &account));
+    }
+
+    let mut output = open_output(&args.output)?;
+    writeln!(output, "client,field,expected,actual")?;
+    let mut differences = 0;
+    let clients = expected.keys().chain(actual.keys()).copied();
+    for client in clients.collect::<BTreeSet<ClientId>>() {
+        let rows = compare(expected.get(&client), actual.get(&client));
+        differences += rows.len();
+        for (field, expected, actual) in rows {
+            writeln!(output, "{client},{field},{expected},{actual}")?;
+        }
+    }
+    output.finish()?;
+    Ok(if differences > 0 {
+        Status::CheckFailed
+    } else if incomplete {
+        Status::Incomplete
+    } else {
+        Status::Success
+    })
+}
+
+/// Compare the expected and actual state of one account, returning `(field, expected, actual)`
+/// for every field that differs.
+fn compare(
+    expected: Option<&AccountRecord>,
+    actual: Option<&AccountRecord>,
+) -> Vec<(&'static str, String, String)> {
+    let (expected, actual) = match (expected, actual) {
+        (Some(expected), Some(actual)) => (expected, actual),
+        (expected, actual) => {
+            let presence = |record: Option<_>| match record {
+                Some(_) => "present".to_string(),
+                None => "missing".to_string(),
+            };
+            return vec![("account", presence(expected), presence(actual))];
+        }
+    };
+    let mut differences = Vec::new();
+    for (field, expected, actual) in [
+        ("available", expected.available, actual.available),
+        ("held", expected.held, actual.held),
+        ("total", expected.total, actual.total),
+    ] {
+        if expected != actual {
+            differences.push((field, expected.to_string(), actual.to_string()));
+        }
+    }
+    if expected.locked != actual.locked {
+        differences.push((
+            "locked",
+            expected.locked.to_string(),
+            actual.locked.to_string(),
+        ));
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(client: ClientId, available: f64, held: f64, locked: bool) -> AccountRecord {
+        AccountRecord {
+            client,
+            available,
+            held,
+            total: available + held,
+            locked,
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let expected = record(1, 1.5, 0.0, false);
+        assert!(compare(Some(&expected), Some(&expected)).is_empty());
+        assert_eq!(
+            compare(Some(&expected), Some(&record(1, 1.0, 0.5, true))),
+            [
+                ("available", "1.5".to_string(), "1".to_string()),
+                ("held", "0".to_string(), "0.5".to_string()),
+                ("locked", "false".to_string(), "true".to_string()),
+            ]
+        );
+        assert_eq!(
+            compare(None, Some(&expected)),
+            [("account", "missing".to_string(), "present".to_string())]
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use super::{Status, open_output};
+use crate::account::Accounts;
+use crate::cli::ReplayArgs;
+use crate::io::AccountRecord;
+use std::io::Write;
+
+/// Apply the transactions one by one to a single `Accounts` on the calling thread, and write a csv
+/// row per transaction with its outcome and the state of the client's account after it. This is
+/// meant for investigating the history of individual clients, not for throughput.
+pub fn run(args: ReplayArgs) -> super::Result {
+    let mut output = open_output(&args.output)?;
+    writeln!(
+        output,
+        "type,client,tx,amount,result,available,held,total,locked"
+    )?;
+    let mut accounts = Accounts::default();
+    for tx in super::transaction_reader(args.input)? {
+        let tx = tx?;
+        let result = tx.execute_transaction(&mut accounts);
+        if !args.clients.is_empty() && !args.clients.contains(&tx.client()) {
+            continue;
+        }
+        write!(
+            output,
+            "{},{},{},",
+            tx.tx_type().as_str(),
+            tx.client(),
+            tx.tx()
+        )?;
+        if let Some(amount) = tx.amount() {
+            write!(output, "{amount}")?;
+        }
+        match result {
+            Ok(()) => write!(output, ",ok,")?,
+            Err(e) => write!(output, ",{e},")?,
+        }
+        let account = AccountRecord::new(tx.client(), accounts.client_account(tx.client()));
+        writeln!(
+            output,
+            "{},{},{},{}",
+            account.available, account.held, account.total, account.locked
+        )?;
+    }
+    output.finish()?;
+    Ok(Status::Success)
+}
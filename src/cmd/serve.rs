@@ -0,0 +1,87 @@
+use super::{Status, placement, report_placement, shard_assigner};
+use crate::cli::ServeArgs;
+use crate::rt::rebalance::RebalancePolicy;
+use crate::server;
+use std::net::TcpListener;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::mpsc::{Sender, channel};
+use std::thread::spawn;
+
+/// Serve clients on every address given until the process is killed, see `crate::server`. The
+/// accounts only live in memory, and are lost when the server stops.
+///
+/// # Errors
+/// If an address can't be served, or one of the servers stops, which only happens if it panicked.
+pub fn run(args: ServeArgs) -> super::Result {
+    let placement = placement(&args.runtime)?;
+    let assigner = shard_assigner(&args.runtime, placement.max_threads)?;
+    if args.runtime.verbose {
+        report_placement(&placement, false)?;
+    }
+    let runtime = server::runtime(placement, assigner, args.rebalance)?;
+    let (stopped, first_stopped) = channel();
+    if let Some(addr) = args.listen.listen {
+        let listener = TcpListener::bind(&addr)?;
+        eprintln!("listening on {}", listener.local_addr()?);
+        let runtime = runtime.clone();
+        spawn_server(&stopped, "tcp", move || {
+            server::serve_tcp(listener, runtime)
+        });
+    }
+    if let Some(path) = args.listen.unix {
+        #[cfg(unix)]
+        {
+            let listener = std::os::unix::net::UnixListener::bind(&path)?;
+            eprintln!("listening on {}", path.display());
+            let runtime = runtime.clone();
+            spawn_server(&stopped, "unix", move || {
+                server::serve_unix(listener, runtime)
+            });
+        }
+        #[cfg(not(unix))]
+        return Err(format!("{}: Unix sockets are not supported", path.display()).into());
+    }
+    #[cfg(feature = "http")]
+    if let Some(addr) = args.listen.http {
+        let http = tiny_http::Server::http(&addr).map_err(|e| format!("{addr}: {e}"))?;
+        eprintln!("serving http on {}", http.server_addr());
+        let runtime = runtime.clone();
+        spawn_server(&stopped, "http", move || {
+            server::http::serve_http(http, runtime)
+        });
+    }
+    if args.rebalance {
+        let runtime = runtime.clone();
+        spawn_server(&stopped, "rebalance", move || {
+            server::rebalance(runtime, RebalancePolicy::default())
+        });
+    }
+    if let Some(addr) = args.metrics {
+        let listener = TcpListener::bind(&addr)?;
+        eprintln!("serving metrics on {}", listener.local_addr()?);
+        let runtime = runtime.clone();
+        spawn_server(&stopped, "metrics", move || {
+            server::metrics::serve_metrics(listener, runtime)
+        });
+    }
+    drop(stopped);
+    match first_stopped.recv() {
+        Ok(name) => Err(format!("the {name} server stopped").into()),
+        // nothing was served
+        Err(_) => Ok(Status::Success),
+    }
+}
+
+/// Run `server` on its own thread, and send its `name` over `stopped` once it returns or panics.
+/// The panic is printed by the panic hook, and fails the command instead of aborting it.
+fn spawn_server(
+    stopped: &Sender<&'static str>,
+    name: &'static str,
+    server: impl FnOnce() + Send + 'static,
+) {
+    let stopped = stopped.clone();
+    spawn(move || {
+        let _ = catch_unwind(AssertUnwindSafe(server));
+        let _ = stopped.send(name);
+    });
+}
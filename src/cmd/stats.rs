@@ -0,0 +1,53 @@
+use super::{Status, open_output, placement, shard_assigner};
+use crate::account::ClientId;
+use crate::cli::StatsArgs;
+use crate::io::CsvTransactionType;
+use crate::rt::Shardable;
+use fnv::FnvHashMap;
+use std::io::Write;
+
+/// Print statistics about the transactions, including how they would be distributed over the
+/// shards of the runtime, without processing them.
+pub fn run(args: StatsArgs) -> super::Result {
+    let threads = placement(&args.runtime)?.max_threads;
+    let assigner = shard_assigner(&args.runtime, threads)?;
+    let mut per_type = [0u64; CsvTransactionType::ALL.len()];
+    let mut per_client = FnvHashMap::<ClientId, u64>::default();
+    let mut per_shard = vec![0u64; threads];
+    let mut deposited = 0f64;
+    let mut withdrawn = 0f64;
+    for tx in super::transaction_reader(args.input)? {
+        let tx = tx?;
+        per_type[tx.tx_type() as usize] += 1;
+        *per_client.entry(tx.client()).or_default() += 1;
+        per_shard[assigner.assign(tx.shard_key(), threads)] += 1;
+        match tx.tx_type() {
+            CsvTransactionType::Deposit => deposited += tx.amount().unwrap_or(0.0) as f64,
+            CsvTransactionType::Withdrawal => withdrawn += tx.amount().unwrap_or(0.0) as f64,
+            _ => {}
+        }
+    }
+
+    let mut output = open_output(&args.output)?;
+    writeln!(output, "transactions: {}", per_type.iter().sum::<u64>())?;
+    for tx_type in CsvTransactionType::ALL {
+        writeln!(
+            output,
+            "  {}: {}",
+            tx_type.as_str(),
+            per_type[tx_type as usize]
+        )?;
+    }
+    writeln!(output, "deposited: {deposited}")?;
+    writeln!(output, "withdrawn: {withdrawn}")?;
+    writeln!(output, "clients: {}", per_client.len())?;
+    if let Some((client, count)) = per_client.iter().max_by_key(|(_, count)| **count) {
+        writeln!(output, "busiest client: {client} ({count} transactions)")?;
+    }
+    writeln!(output, "transactions per shard ({threads} shards):")?;
+    for (shard, count) in per_shard.iter().enumerate() {
+        writeln!(output, "  {shard}: {count}")?;
+    }
+    output.finish()?;
+    Ok(Status::Success)
+}
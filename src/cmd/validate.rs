@@ -0,0 +1,311 @@
+use super::{Status, open_output};
+use crate::account::{Accounts, Amount, TransactionError};
+use crate::cli::ValidateArgs;
+use crate::io::binary::BinaryError;
+use crate::io::{CsvTransaction, CsvTransactionType, ReadError};
+use serde::Deserialize;
+use serde::de::value::{self, StrDeserializer};
+use std::io::Write;
+
+/// A category of problems reported by `validate`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Category {
+    /// A transaction that can't be read for another reason than its type or amount
+    Malformed,
+    UnknownType,
+    /// An amount that can't be parsed, or a missing, non-positive or non-finite amount of a deposit
+    /// or withdrawal
+    BadAmount,
+    /// A deposit reusing the id of an earlier deposit of the same client. Like the engine, ids
+    /// are not checked across clients, nor for withdrawals.
+    DuplicateTx,
+    /// A dispute, resolve or chargeback of a transaction the client never deposited
+    UnknownTx,
+    /// A resolve or chargeback of a transaction that isn't disputed, a dispute of a transaction
+    /// that already is, or any transaction after a chargeback locked the account
+    OutOfOrder,
+}
+
+impl Category {
+    const ALL: [Category; 6] = [
+        Category::Malformed,
+        Category::UnknownType,
+        Category::BadAmount,
+        Category::DuplicateTx,
+        Category::UnknownTx,
+        Category::OutOfOrder,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Category::Malformed => "malformed",
+            Category::UnknownType => "unknown_type",
+            Category::BadAmount => "bad_amount",
+            Category::DuplicateTx => "duplicate_tx",
+            Category::UnknownTx => "unknown_tx",
+            Category::OutOfOrder => "out_of_order",
+        }
+    }
+}
+
+/// Checks transactions against the business rules by applying them to `Accounts`, like the
+/// process command would, but on a single thread and without writing balances.
+#[derive(Default)]
+struct Linter {
+    accounts: Accounts,
+}
+
+impl Linter {
+    /// Apply `tx` and return the problem it has, if any. Insufficient funds are not a problem of
+    /// the input, so they are not reported.
+    fn check(&mut self, tx: &CsvTransaction) -> Option<(Category, String)> {
+        let tx_type = tx.tx_type().as_str();
+        let tx_id = tx.tx();
+        match tx.execute_transaction(&mut self.accounts).err()? {
+            TransactionError::InvalidAmount => Some((
+                Category::BadAmount,
+                match tx.amount() {
+                    Some(amount) if amount.is_finite() => {
+                        format!("{tx_type} of non-positive amount {amount}")
+                    }
+                    Some(amount) => format!("{tx_type} of non-finite amount {amount}"),
+                    None => format!("{tx_type} without an amount"),
+                },
+            )),
+            TransactionError::TransactionNotFound => Some((
+                Category::UnknownTx,
+                format!(
+                    "{tx_type} of transaction {tx_id}, which is not a deposit of client {}",
+                    tx.client()
+                ),
+            )),
+            TransactionError::NotDisputed => Some((
+                Category::OutOfOrder,
+                format!("{tx_type} of transaction {tx_id}, which is not disputed"),
+            )),
+            TransactionError::AlreadyDisputed => Some((
+                Category::OutOfOrder,
+                format!("{tx_type} of transaction {tx_id}, which is already disputed"),
+            )),
+            TransactionError::AccountLocked => Some((
+                Category::OutOfOrder,
+                format!(
+                    "{tx_type} after a chargeback locked the account of client {}",
+                    tx.client()
+                ),
+            )),
+            TransactionError::DuplicateTransaction => Some((
+                Category::DuplicateTx,
+                format!("{tx_type} reuses transaction id {tx_id}"),
+            )),
+            TransactionError::InsufficientFunds => None,
+        }
+    }
+}
+
+/// Categorize a transaction that can't be read, returning `None` if the input itself can't be read
+/// rather than one of its transactions.
+fn categorize(error: &ReadError) -> Option<(Category, String)> {
+    match error {
+        ReadError::Csv { source, tx_type } => match source.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => {
+                let category = if tx_type.as_deref().is_some_and(is_unknown_type) {
+                    Category::UnknownType
+                } else if let csv::DeserializeErrorKind::ParseFloat(_) = err.kind() {
+                    // the amount is the only floating point field
+                    Category::BadAmount
+                } else {
+                    Category::Malformed
+                };
+                Some((category, err.to_string()))
+            }
+            csv::ErrorKind::Io(_) => None,
+            _ => Some((Category::Malformed, source.to_string())),
+        },
+        ReadError::Json { source, value, .. } => {
+            let category = match source.classify() {
+                serde_json::error::Category::Io => return None,
+                serde_json::error::Category::Syntax | serde_json::error::Category::Eof => {
+                    Category::Malformed
+                }
+                serde_json::error::Category::Data => value
+                    .as_deref()
+                    .map_or(Category::Malformed, categorize_json),
+            };
+            Some((category, source.to_string()))
+        }
+        ReadError::Binary { kind, .. } => {
+            let category = match kind {
+                BinaryError::UnknownTransactionType(_) => Category::UnknownType,
+                BinaryError::UnrepresentableAmount(_) => Category::BadAmount,
+                BinaryError::TruncatedRecord => Category::Malformed,
+            };
+            Some((category, kind.to_string()))
+        }
+        ReadError::Io(_) => None,
+        ReadError::Columns { .. } => Some((Category::Malformed, error.to_string())),
+        ReadError::Input { source, .. } => categorize(source),
+    }
+}
+
+/// Whether the type column of a csv row is not a `CsvTransactionType`.
+fn is_unknown_type(tx_type: &str) -> bool {
+    let tx_type = StrDeserializer::<value::Error>::new(tx_type);
+    CsvTransactionType::deserialize(tx_type).is_err()
+}
+
+/// Categorize a json line that is valid json but not a transaction by the first field that can't
+/// be deserialized, as serde_json doesn't tell which field failed.
+fn categorize_json(value: &serde_json::Value) -> Category {
+    let tx_type = value.get("type").filter(|tx_type| tx_type.is_string());
+    let amount = value.get("amount").filter(|amount| !amount.is_null());
+    if tx_type.is_some_and(|tx_type| CsvTransactionType::deserialize(tx_type).is_err()) {
+        Category::UnknownType
+    } else if amount.is_some_and(|amount| Amount::deserialize(amount).is_err()) {
+        Category::BadAmount
+    } else {
+        Category::Malformed
+    }
+}
+
+/// Read all transactions and check them against the business rules, reporting every problem
+/// instead of stopping at the first one like the other commands. Every problem is written as
+/// `input:line: category: message`, followed by the number of problems per category.
+///
+/// # Errors
+/// Only if an input or the output can't be read or written. Problems with the transactions make
+/// the check fail instead.
+pub fn run(args: ValidateArgs) -> super::Result {
+    let mut output = open_output(&args.output)?;
+    let mut reader = super::transaction_reader(args.input)?;
+    let mut linter = Linter::default();
+    let mut transactions = 0u64;
+    let mut counts = [0u64; Category::ALL.len()];
+    while let Some(tx) = reader.next() {
+        let (line, problem) = match tx {
+            Ok(tx) => {
+                transactions += 1;
+                let line = reader.position().map(|(_, line)| line);
+                (line, linter.check(&tx))
+            }
+            Err(e) => match categorize(&e) {
+                Some(problem) => (e.line(), Some(problem)),
+                None => return Err(e.into()),
+            },
+        };
+        if let Some((category, message)) = problem {
+            counts[category as usize] += 1;
+            let input = reader.position().map(|(input, _)| input.to_string());
+            let input = input.unwrap_or_default();
+            let line = line.unwrap_or_default();
+            writeln!(output, "{input}:{line}: {}: {message}", category.as_str())?;
+        }
+    }
+
+    let problems = counts.iter().sum::<u64>();
+    writeln!(output, "transactions: {transactions}")?;
+    writeln!(output, "problems: {problems}")?;
+    for category in Category::ALL {
+        writeln!(
+            output,
+            "  {}: {}",
+            category.as_str(),
+            counts[category as usize]
+        )?;
+    }
+    output.finish()?;
+    Ok(if problems == 0 {
+        Status::Success
+    } else {
+        Status::CheckFailed
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Format, TransactionReader, csv_transaction_reader};
+
+    fn lint_csv(csv: &str) -> Vec<Option<Category>> {
+        let mut linter = Linter::default();
+        csv_transaction_reader(csv.as_bytes())
+            .map(|tx| match tx {
+                Ok(tx) => linter.check(&tx),
+                Err(e) => categorize(&e),
+            })
+            .map(|problem| problem.map(|(category, _)| category))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        let csv = "type, client, tx, amount\n\
+            deposit, 1, 1, 5.0\n\
+            refund, 1, 2, 1.0\n\
+            deposit, 1, 3, abc\n\
+            withdrawal, 1, 4\n\
+            deposit, 2, 1, 1.0\n\
+            deposit, 1, 1, 1.0\n\
+            dispute, 1, 9\n\
+            resolve, 1, 1\n\
+            withdrawal, 1, 5, 10.0\n\
+            dispute, 1, 1\n\
+            dispute, 1, 1\n\
+            chargeback, 1, 1\n\
+            deposit, 1, 6, 1.0\n\
+            deposit, one, 7, 1.0\n\
+            deposit, 2, 8, NaN\n\
+            deposit, 2, 9, 1.0, 1";
+
+        assert_eq!(
+            lint_csv(csv),
+            [
+                None,
+                Some(Category::UnknownType),
+                Some(Category::BadAmount),
+                Some(Category::BadAmount),
+                None,
+                Some(Category::DuplicateTx),
+                Some(Category::UnknownTx),
+                Some(Category::OutOfOrder),
+                None,
+                None,
+                Some(Category::OutOfOrder),
+                None,
+                Some(Category::OutOfOrder),
+                Some(Category::Malformed),
+                Some(Category::BadAmount),
+                Some(Category::Malformed),
+            ]
+        );
+        // the type column is found by its header
+        assert_eq!(
+            lint_csv("client, tx, type\n1, 1, refund\n1, 2, deposit"),
+            [Some(Category::UnknownType), Some(Category::BadAmount)]
+        );
+    }
+
+    #[test]
+    fn test_categorize_jsonl() {
+        let jsonl = r#"{"type": "refund", "client": 1, "tx": 1}
+            {"type": "deposit", "client": 1, "tx": 1, "amount": "abc"}
+            {"type": "deposit", "client": 1}
+            {"type": "deposit", "client": "expected f32", "tx": 1}
+            {"type": "unknown variant", "client": 1, "tx": 1, "amount": "abc"}
+            {"type": "deposit", "client": 1, "tx": 1, "amount": 1.0"#;
+        let categories: Vec<_> = TransactionReader::new(Format::Jsonl, jsonl.as_bytes())
+            .map(|tx| categorize(&tx.err().unwrap()).unwrap().0)
+            .collect();
+        assert_eq!(
+            categories,
+            [
+                Category::UnknownType,
+                Category::BadAmount,
+                Category::Malformed,
+                Category::Malformed,
+                Category::UnknownType,
+                Category::Malformed,
+            ]
+        );
+    }
+}
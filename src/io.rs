@@ -1,11 +1,22 @@
 use crate::account::{Account, Accounts, Amount, ClientId, TransactionError, TxId};
 use crate::rt::Shardable;
+use crate::rt::outcome::Quarantine;
+use crate::rt::rebalance::Migrate;
 use csv::Trim;
-use serde::Deserialize;
-use std::io::{Read, Write};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::str::FromStr;
+
+pub mod binary;
+pub mod compression;
+pub mod input;
 
 /// Represents a transaction type in the csv input format
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum CsvTransactionType {
     Deposit,
@@ -15,44 +26,525 @@ pub enum CsvTransactionType {
     Chargeback,
 }
 
-/// Represents a single transaction in the csv input format
-#[derive(Deserialize)]
+impl CsvTransactionType {
+    pub const ALL: [CsvTransactionType; 5] = [
+        CsvTransactionType::Deposit,
+        CsvTransactionType::Withdrawal,
+        CsvTransactionType::Dispute,
+        CsvTransactionType::Resolve,
+        CsvTransactionType::Chargeback,
+    ];
+
+    /// The name of the type in the csv input format
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CsvTransactionType::Deposit => "deposit",
+            CsvTransactionType::Withdrawal => "withdrawal",
+            CsvTransactionType::Dispute => "dispute",
+            CsvTransactionType::Resolve => "resolve",
+            CsvTransactionType::Chargeback => "chargeback",
+        }
+    }
+}
+
+/// Represents a single transaction in the csv input format. This is also the internal
+/// representation of transactions read from any of the other supported formats.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct CsvTransaction {
     #[serde(rename = "type")]
     tx_type: CsvTransactionType,
     client: ClientId,
     tx: TxId,
-    amount: Amount,
+    // disputes, resolves and chargebacks refer to an earlier deposit and carry no amount
+    #[serde(default)]
+    amount: Option<Amount>,
 }
 
 impl CsvTransaction {
+    pub fn new(
+        tx_type: CsvTransactionType,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Amount>,
+    ) -> Self {
+        Self {
+            tx_type,
+            client,
+            tx,
+            amount,
+        }
+    }
+
     /// Execute the appropriate method on `Accounts` based on the transaction type.
+    ///
+    /// # Errors
+    /// - `InvalidAmount` if a deposit or withdrawal has no amount
+    /// - Any error returned by the executed `Accounts` method
     pub fn execute_transaction(&self, accounts: &mut Accounts) -> Result<(), TransactionError> {
         match self.tx_type {
-            CsvTransactionType::Deposit => accounts.deposit(self.client, self.tx, self.amount),
-            CsvTransactionType::Withdrawal => accounts.withdraw(self.client, self.amount),
+            CsvTransactionType::Deposit => {
+                accounts.deposit(self.client, self.tx, self.required_amount()?)
+            }
+            CsvTransactionType::Withdrawal => {
+                accounts.withdraw(self.client, self.required_amount()?)
+            }
             CsvTransactionType::Dispute => accounts.dispute(self.client, self.tx),
             CsvTransactionType::Resolve => accounts.resolve(self.client, self.tx),
             CsvTransactionType::Chargeback => accounts.chargeback(self.client, self.tx),
         }
     }
+
+    #[inline]
+    fn required_amount(&self) -> Result<Amount, TransactionError> {
+        self.amount.ok_or(TransactionError::InvalidAmount)
+    }
+
+    pub fn tx_type(&self) -> CsvTransactionType {
+        self.tx_type
+    }
+
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn tx(&self) -> TxId {
+        self.tx
+    }
+
+    pub fn amount(&self) -> Option<Amount> {
+        self.amount
+    }
 }
 
 /// Allows a transaction to be submitted for processing on a `crate::rt::ShardedThreadPerCoreRuntime`
 impl Shardable for CsvTransaction {
-    fn shard_id(&self, num_shards: u8) -> usize {
-        self.client as usize % num_shards as usize
+    fn shard_key(&self) -> u64 {
+        self.client.shard_key()
+    }
+}
+
+/// Keeps a copy of a transaction that panicked on a `crate::rt::ShardedThreadPerCoreRuntime`
+impl Quarantine for CsvTransaction {
+    type Record = CsvTransaction;
+
+    fn record(&self) -> CsvTransaction {
+        self.clone()
+    }
+}
+
+/// Writes a transaction as a csv row without a line break, in the columns `type,client,tx,amount`
+impl Display for CsvTransaction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{},", self.tx_type.as_str(), self.client, self.tx)?;
+        match self.amount {
+            Some(amount) => write!(f, "{amount}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Routes a client to the shard that processes its transactions, e.g. to query its account with
+/// `crate::rt::ShardedThreadPerCoreRuntime::call`
+impl Shardable for ClientId {
+    fn shard_key(&self) -> u64 {
+        *self as u64
+    }
+}
+
+/// Allows the account of a client to be moved to another shard with
+/// `crate::rt::ShardedThreadPerCoreRuntime::migrate`
+impl Migrate for Accounts {
+    type Part = Account;
+
+    fn take(&mut self, key: u64) -> Option<Account> {
+        self.remove(key as ClientId)
+    }
+
+    fn put(&mut self, key: u64, account: Account) {
+        self.insert(key as ClientId, account);
+    }
+}
+
+/// The file formats supported for reading transactions and writing accounts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    /// Newline delimited json, one object per line
+    Jsonl,
+    /// Fixed-width binary records, see `binary`. Only supported for transactions.
+    Binary,
+}
+
+impl Format {
+    /// Guess the format from the extension of `path`, e.g. `transactions.jsonl`. A compression
+    /// extension is skipped, so `transactions.jsonl.gz` is also json lines.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let path = match compression::Compression::from_path(path) {
+            Some(_) => Path::new(path.file_stem()?),
+            None => path,
+        };
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "jsonl" | "ndjson" => Ok(Format::Jsonl),
+            "bin" | "binary" => Ok(Format::Binary),
+            _ => Err(format!(
+                "unknown format '{s}', expected one of: csv, jsonl, binary"
+            )),
+        }
+    }
+}
+
+/// An error encountered while reading transactions.
+#[derive(Debug)]
+pub enum ReadError {
+    Csv {
+        source: csv::Error,
+        /// The type column of the row, if it was read but is not a valid transaction
+        tx_type: Option<String>,
+    },
+    Json {
+        line: u64,
+        source: serde_json::Error,
+        /// The line as a json value, if it is valid json but not a valid record
+        value: Option<Box<serde_json::Value>>,
+    },
+    Binary {
+        record: u64,
+        kind: binary::BinaryError,
+    },
+    Io(std::io::Error),
+    /// A csv row with more columns than the header
+    Columns {
+        line: u64,
+        columns: usize,
+        expected: usize,
+    },
+    /// An error in one of several inputs, see `input::MultiInputReader`
+    Input {
+        input: String,
+        source: Box<ReadError>,
+    },
+}
+
+/// An error of the csv reader itself, rather than of a row it read
+impl From<csv::Error> for ReadError {
+    fn from(source: csv::Error) -> Self {
+        ReadError::Csv {
+            source,
+            tx_type: None,
+        }
+    }
+}
+
+impl ReadError {
+    /// The line the error occurred on, or the record number for the binary format, if known.
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            ReadError::Csv { source, .. } => source.position().map(|position| position.line()),
+            ReadError::Json { line, .. } => Some(*line),
+            ReadError::Binary { record, .. } => Some(*record),
+            ReadError::Io(_) => None,
+            ReadError::Columns { line, .. } => Some(*line),
+            ReadError::Input { source, .. } => source.line(),
+        }
+    }
+
+    /// Whether the error is confined to one record, like a field that can't be parsed, so the
+    /// records after it can still be read. Errors reading the input itself, like an input that
+    /// can't be opened or decompressed, or that ends in the middle of a record, are not.
+    pub fn is_record_error(&self) -> bool {
+        match self {
+            ReadError::Csv { source, .. } => !source.is_io_error(),
+            ReadError::Json { source, .. } => !source.is_io(),
+            ReadError::Binary { kind, .. } => !matches!(kind, binary::BinaryError::TruncatedRecord),
+            ReadError::Io(_) => false,
+            ReadError::Columns { .. } => true,
+            ReadError::Input { source, .. } => source.is_record_error(),
+        }
+    }
+
+    fn in_input(input: &input::Input, source: ReadError) -> Self {
+        ReadError::Input {
+            input: input.to_string(),
+            source: Box::new(source),
+        }
+    }
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Csv { source, .. } => write!(f, "{source}"),
+            ReadError::Json { line, source, .. } => write!(f, "line {line}: {source}"),
+            ReadError::Binary { record, kind } => write!(f, "record {record}: {kind}"),
+            ReadError::Io(e) => write!(f, "{e}"),
+            ReadError::Columns {
+                line,
+                columns,
+                expected,
+            } => write!(
+                f,
+                "line {line}: {columns} columns, but the header has {expected}"
+            ),
+            ReadError::Input { input, source } => write!(f, "{input}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// A reader for transactions in any of the supported formats.
+pub enum TransactionReader<R: Read> {
+    Csv(CsvTransactionReader<R>),
+    Jsonl(JsonlTransactionReader<R>),
+    Binary(binary::BinaryTransactionReader<R>),
+}
+
+impl<R: Read> TransactionReader<R> {
+    pub fn new(format: Format, reader: R) -> Self {
+        match format {
+            Format::Csv => TransactionReader::Csv(csv_transaction_reader(reader)),
+            Format::Jsonl => TransactionReader::Jsonl(JsonlReader::new(reader)),
+            Format::Binary => {
+                TransactionReader::Binary(binary::BinaryTransactionReader::new(reader))
+            }
+        }
+    }
+
+    /// The line of the transaction last read, or its record number for the binary format.
+    pub fn position(&self) -> u64 {
+        match self {
+            TransactionReader::Csv(reader) => reader.line,
+            TransactionReader::Jsonl(reader) => reader.line_number,
+            TransactionReader::Binary(reader) => reader.position(),
+        }
+    }
+}
+
+impl<R: Read> Iterator for TransactionReader<R> {
+    type Item = Result<CsvTransaction, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            TransactionReader::Csv(reader) => reader.next(),
+            TransactionReader::Jsonl(reader) => reader.next(),
+            TransactionReader::Binary(reader) => reader.next(),
+        }
     }
 }
 
 /// A reader for the csv input format.
-pub fn csv_transaction_reader<R: Read>(
-    reader: R,
-) -> csv::DeserializeRecordsIntoIter<R, CsvTransaction> {
-    csv::ReaderBuilder::new()
+pub fn csv_transaction_reader<R: Read>(reader: R) -> CsvTransactionReader<R> {
+    let mut reader = csv_reader_builder().from_reader(reader);
+    // like `csv::Reader::into_deserialize`, a header that can't be read is reported by the
+    // first record instead
+    let headers = reader.headers().ok().cloned();
+    CsvTransactionReader {
+        reader,
+        headers,
+        record: csv::StringRecord::new(),
+        line: 0,
+    }
+}
+
+/// A reader for csv lines without a header, like the lines streamed to a `crate::server`. A line
+/// holding a transaction has the columns `type, client, tx, amount`, and can be deserialized into
+/// a `CsvTransaction` with `csv::StringRecord::deserialize`.
+pub fn csv_line_reader<R: Read>(reader: R) -> csv::Reader<R> {
+    csv_reader_builder().has_headers(false).from_reader(reader)
+}
+
+fn csv_reader_builder() -> csv::ReaderBuilder {
+    let mut builder = csv::ReaderBuilder::new();
+    builder
         .trim(Trim::All)
-        .from_reader(reader)
-        .into_deserialize()
+        // allow the amount column to be left out for disputes, resolves and chargebacks, rows
+        // with more columns are still rejected by `CsvTransactionReader` and the server
+        .flexible(true);
+    builder
+}
+
+/// A reader for the csv input format, see `csv_transaction_reader`. Unlike the iterators of the
+/// csv crate, it keeps track of the line of the transaction last read.
+pub struct CsvTransactionReader<R> {
+    reader: csv::Reader<R>,
+    headers: Option<csv::StringRecord>,
+    // reused between records to avoid an allocation per transaction
+    record: csv::StringRecord,
+    line: u64,
+}
+
+impl<R> CsvTransactionReader<R> {
+    /// The type column of the record last read, which is the first column without a header.
+    fn tx_type(&self) -> Option<&str> {
+        let column = match &self.headers {
+            Some(headers) => headers.iter().position(|header| header == "type")?,
+            None => 0,
+        };
+        self.record.get(column)
+    }
+}
+
+impl<R: Read> Iterator for CsvTransactionReader<R> {
+    type Item = Result<CsvTransaction, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_record(&mut self.record) {
+            Ok(true) => {
+                self.line = self.record.position().map_or(0, |position| position.line());
+                if let Some(headers) = &self.headers
+                    && self.record.len() > headers.len()
+                {
+                    return Some(Err(ReadError::Columns {
+                        line: self.line,
+                        columns: self.record.len(),
+                        expected: headers.len(),
+                    }));
+                }
+                Some(
+                    self.record
+                        .deserialize(self.headers.as_ref())
+                        .map_err(|source| ReadError::Csv {
+                            source,
+                            tx_type: self.tx_type().map(str::to_string),
+                        }),
+                )
+            }
+            Ok(false) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+/// A reader for the json lines input format, where every non-empty line holds one transaction
+/// object with the same fields as the csv format, e.g.
+/// `{"type": "deposit", "client": 1, "tx": 1, "amount": 1.0}`.
+pub type JsonlTransactionReader<R> = JsonlReader<R, CsvTransaction>;
+
+/// A reader for json lines, deserializing every non-empty line into a `T`.
+pub struct JsonlReader<R, T> {
+    reader: BufReader<R>,
+    // reused between lines to avoid an allocation per transaction
+    line: String,
+    line_number: u64,
+    _t: PhantomData<T>,
+}
+
+impl<R: Read, T> JsonlReader<R, T> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            line: String::new(),
+            line_number: 0,
+            _t: PhantomData,
+        }
+    }
+}
+
+impl<R: Read, T: DeserializeOwned> Iterator for JsonlReader<R, T> {
+    type Item = Result<T, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => self.line_number += 1,
+                Err(e) => return Some(Err(ReadError::Io(e))),
+            }
+            let line = self.line.trim();
+            if !line.is_empty() {
+                return Some(serde_json::from_str(line).map_err(|source| {
+                    let value = if source.is_data() {
+                        serde_json::from_str(line).ok().map(Box::new)
+                    } else {
+                        None
+                    };
+                    ReadError::Json {
+                        line: self.line_number,
+                        source,
+                        value,
+                    }
+                }));
+            }
+        }
+    }
+}
+
+/// A writer of client accounts in one of the supported output formats.
+pub trait AccountWriter {
+    fn write_header(&mut self) -> std::io::Result<()>;
+
+    fn write_account(&mut self, client_id: ClientId, account: &Account) -> std::io::Result<()>;
+
+    fn flush(&mut self) -> std::io::Result<()>;
+}
+
+/// A writer for accounts in any of the supported output formats.
+pub enum AnyAccountWriter<W: Write> {
+    Csv(AccountCsvWriter<W>),
+    Jsonl(AccountJsonWriter<W>),
+}
+
+impl<W: Write> AnyAccountWriter<W> {
+    /// Create an `AccountWriter` for `format`.
+    ///
+    /// # Errors
+    /// An error of kind `Unsupported` if accounts can't be written in `format`.
+    pub fn new(format: Format, writer: W) -> std::io::Result<Self> {
+        match format {
+            Format::Csv => Ok(AnyAccountWriter::Csv(AccountCsvWriter::new(writer))),
+            Format::Jsonl => Ok(AnyAccountWriter::Jsonl(AccountJsonWriter::new(writer))),
+            Format::Binary => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "accounts can't be written in the binary format",
+            )),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        match self {
+            AnyAccountWriter::Csv(writer) => writer.writer,
+            AnyAccountWriter::Jsonl(writer) => writer.writer,
+        }
+    }
+}
+
+impl<W: Write> AccountWriter for AnyAccountWriter<W> {
+    fn write_header(&mut self) -> std::io::Result<()> {
+        match self {
+            AnyAccountWriter::Csv(writer) => writer.write_header(),
+            AnyAccountWriter::Jsonl(writer) => writer.write_header(),
+        }
+    }
+
+    fn write_account(&mut self, client_id: ClientId, account: &Account) -> std::io::Result<()> {
+        match self {
+            AnyAccountWriter::Csv(writer) => writer.write_account(client_id, account),
+            AnyAccountWriter::Jsonl(writer) => writer.write_account(client_id, account),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            AnyAccountWriter::Csv(writer) => writer.flush(),
+            AnyAccountWriter::Jsonl(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Our output floats have at most 4 decimal places
+#[inline]
+fn truncate_amount(amount: f64) -> f64 {
+    f64::trunc(amount * 10000.0) / 10000.0
 }
 
 /// A writer for the csv output format.
@@ -64,22 +556,145 @@ impl<W: Write> AccountCsvWriter<W> {
     pub fn new(writer: W) -> Self {
         Self { writer }
     }
+}
 
-    pub fn write_header(&mut self) -> std::io::Result<()> {
+impl<W: Write> AccountWriter for AccountCsvWriter<W> {
+    fn write_header(&mut self) -> std::io::Result<()> {
         writeln!(self.writer, "client,available,held,total,locked")
     }
 
-    pub fn write_account(&mut self, client_id: ClientId, account: &Account) -> std::io::Result<()> {
-        // ensure our output floats have at most 4 decimal places
-        let available = f64::trunc(account.available() * 10000.0) / 10000.0;
-        let held = f64::trunc(account.held() * 10000.0) / 10000.0;
-        let total = f64::trunc(account.total() * 10000.0) / 10000.0;
+    fn write_account(&mut self, client_id: ClientId, account: &Account) -> std::io::Result<()> {
+        let available = truncate_amount(account.available());
+        let held = truncate_amount(account.held());
+        let total = truncate_amount(account.total());
         writeln!(
             self.writer,
             "{client_id},{available},{held},{total},{}",
             account.is_locked()
         )
     }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Represents a single account in the output formats
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AccountRecord {
+    pub client: ClientId,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+impl AccountRecord {
+    /// The record for an account as it is written, with amounts truncated to 4 decimal places.
+    pub fn new(client_id: ClientId, account: &Account) -> Self {
+        Self {
+            client: client_id,
+            available: truncate_amount(account.available()),
+            held: truncate_amount(account.held()),
+            total: truncate_amount(account.total()),
+            locked: account.is_locked(),
+        }
+    }
+}
+
+/// A writer for the json lines output format, one account object per line.
+pub struct AccountJsonWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> AccountJsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> AccountWriter for AccountJsonWriter<W> {
+    /// The json lines format has no header
+    fn write_header(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn write_account(&mut self, client_id: ClientId, account: &Account) -> std::io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &AccountRecord::new(client_id, account))?;
+        writeln!(self.writer)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A reader for accounts in one of the output formats, e.g. the output of an earlier run.
+pub enum AccountRecordReader<R: Read> {
+    Csv(csv::DeserializeRecordsIntoIter<R, AccountRecord>),
+    Jsonl(JsonlReader<R, AccountRecord>),
+}
+
+impl<R: Read> AccountRecordReader<R> {
+    /// Create a reader for accounts in `format`.
+    ///
+    /// # Errors
+    /// An error of kind `Unsupported` if accounts can't be read in `format`.
+    pub fn new(format: Format, reader: R) -> std::io::Result<Self> {
+        match format {
+            Format::Csv => Ok(AccountRecordReader::Csv(
+                csv::ReaderBuilder::new()
+                    .trim(Trim::All)
+                    .from_reader(reader)
+                    .into_deserialize(),
+            )),
+            Format::Jsonl => Ok(AccountRecordReader::Jsonl(JsonlReader::new(reader))),
+            Format::Binary => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "accounts can't be read in the binary format",
+            )),
+        }
+    }
+}
+
+impl<R: Read> Iterator for AccountRecordReader<R> {
+    type Item = Result<AccountRecord, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            AccountRecordReader::Csv(reader) => Some(reader.next()?.map_err(ReadError::from)),
+            AccountRecordReader::Jsonl(reader) => reader.next(),
+        }
+    }
+}
+
+/// A writer for the csv report of transactions rejected by `Accounts`.
+pub struct RejectionCsvWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> RejectionCsvWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn write_header(&mut self) -> std::io::Result<()> {
+        writeln!(self.writer, "type,client,tx,amount,error")
+    }
+
+    /// Write a rejected transaction, where `error` is a `TransactionError`, or another reason the
+    /// transaction was not applied.
+    pub fn write_rejection(
+        &mut self,
+        tx: &CsvTransaction,
+        error: impl Display,
+    ) -> std::io::Result<()> {
+        writeln!(self.writer, "{tx},{error}")
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
 }
 
 #[cfg(test)]
@@ -107,6 +722,142 @@ mod tests {
     }
 
     #[test]
+    fn test_csv_reader_without_amount() {
+        let csv = "type, client, tx, amount\n\
+            deposit, 1, 1, 1.0\n\
+            dispute, 1, 1,\n\
+            resolve, 1, 1\n\
+            dispute, 1, 1";
+
+        let reader = csv_transaction_reader(csv.as_bytes());
+        let mut accounts = Accounts::default();
+        for tx in reader {
+            tx.unwrap().execute_transaction(&mut accounts).unwrap();
+        }
+        assert_eq!(accounts.client_account(1).held(), 1.0);
+    }
+
+    #[test]
+    fn test_csv_reader_short_and_long_rows() {
+        let csv = "type, client, tx, amount\n\
+            deposit, 1, 2\n\
+            dispute, 1\n\
+            deposit, 1, 3, 1.0, 9";
+
+        let mut reader = csv_transaction_reader(csv.as_bytes());
+        // only the amount may be left out, which a deposit or withdrawal can't be applied without
+        let tx = reader.next().unwrap().unwrap();
+        assert_eq!(tx.amount(), None);
+        assert_eq!(
+            tx.execute_transaction(&mut Accounts::default()),
+            Err(TransactionError::InvalidAmount)
+        );
+        assert!(matches!(reader.next(), Some(Err(ReadError::Csv { .. }))));
+        assert!(matches!(
+            reader.next(),
+            Some(Err(ReadError::Columns {
+                line: 4,
+                columns: 5,
+                expected: 4
+            }))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_csv_reader_optional_amount() {
+        let csv = "type, client, tx, amount\n\
+            deposit, 1, 1, 2.0\n\
+            dispute, 1, 1,\n\
+            resolve, 1, 1\n\
+            withdrawal, 1, 2,";
+
+        let mut accounts = Accounts::default();
+        let results = csv_transaction_reader(csv.as_bytes())
+            .map(|tx| {
+                let tx = tx.unwrap();
+                (tx.amount(), tx.execute_transaction(&mut accounts))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [
+                (Some(2.0), Ok(())),
+                (None, Ok(())),
+                (None, Ok(())),
+                (None, Err(TransactionError::InvalidAmount)),
+            ]
+        );
+        assert_eq!(accounts.client_account(1).available(), 2.0);
+    }
+
+    #[test]
+    fn test_jsonl_reader() {
+        let jsonl = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.0}
+            {"type": "deposit", "client": 2, "tx": 2, "amount": 2.0}
+
+            {"type": "withdrawal", "client": 1, "tx": 3, "amount": 0.5}
+            {"type": "dispute", "client": 2, "tx": 2}
+            {"type": "withdrawal", "client": 2, "tx": 4}"#;
+
+        let reader = TransactionReader::new(Format::Jsonl, jsonl.as_bytes());
+        let mut accounts = Accounts::default();
+        let results = reader
+            .map(|tx| tx.unwrap().execute_transaction(&mut accounts))
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            results.as_slice(),
+            [
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::InvalidAmount)
+            ]
+        ));
+        assert_eq!(accounts.client_account(1).available(), 0.5);
+        assert_eq!(accounts.client_account(2).held(), 2.0);
+    }
+
+    #[test]
+    fn test_jsonl_reader_error_line() {
+        let jsonl = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1.0}\n\
+            \n\
+            {\"type\": \"transfer\", \"client\": 1, \"tx\": 2, \"amount\": 1.0}\n";
+
+        let mut reader = TransactionReader::new(Format::Jsonl, jsonl.as_bytes());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(ReadError::Json { line: 3, .. }))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path(Path::new("in.csv")), Some(Format::Csv));
+        assert_eq!(
+            Format::from_path(Path::new("in.JSONL")),
+            Some(Format::Jsonl)
+        );
+        assert_eq!(
+            Format::from_path(Path::new("in.ndjson")),
+            Some(Format::Jsonl)
+        );
+        assert_eq!(Format::from_path(Path::new("in.bin")), Some(Format::Binary));
+        assert_eq!(Format::from_path(Path::new("in.csv.gz")), Some(Format::Csv));
+        assert_eq!(
+            Format::from_path(Path::new("in.jsonl.zst")),
+            Some(Format::Jsonl)
+        );
+        assert_eq!(Format::from_path(Path::new("in.gz")), None);
+        assert_eq!(Format::from_path(Path::new("in.txt")), None);
+        assert_eq!(Format::from_path(Path::new("in")), None);
+    }
+
+    #[test]
+    #[allow(clippy::unnecessary_sort_by)]
     fn test_csv_writer() {
         let mut writer = AccountCsvWriter::new(Vec::new());
         writer.write_header().unwrap();
@@ -126,4 +877,70 @@ mod tests {
             2,0,2.1234,2.1234,false\n"
         );
     }
+
+    #[test]
+    fn test_json_writer() {
+        let mut writer = AccountJsonWriter::new(Vec::new());
+        writer.write_header().unwrap();
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, 1, 1.123456).unwrap();
+        accounts.dispute(1, 1).unwrap();
+        accounts.chargeback(1, 1).unwrap();
+        accounts.deposit(2, 2, 2.5).unwrap();
+        for (client_id, account) in accounts.into_sorted_vec() {
+            writer.write_account(client_id, &account).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(writer.writer).unwrap(),
+            "{\"client\":1,\"available\":0.0,\"held\":0.0,\"total\":0.0,\"locked\":true}\n\
+            {\"client\":2,\"available\":2.5,\"held\":0.0,\"total\":2.5,\"locked\":false}\n"
+        );
+    }
+
+    #[test]
+    fn test_account_record_reader() {
+        let mut writer = AnyAccountWriter::new(Format::Csv, Vec::new()).unwrap();
+        writer.write_header().unwrap();
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, 1, 1.5).unwrap();
+        for (client_id, account) in accounts.into_sorted_vec() {
+            writer.write_account(client_id, &account).unwrap();
+        }
+        let csv = writer.into_inner();
+        let records = AccountRecordReader::new(Format::Csv, csv.as_slice())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            records,
+            [AccountRecord {
+                client: 1,
+                available: 1.5,
+                held: 0.0,
+                total: 1.5,
+                locked: false
+            }]
+        );
+    }
+
+    #[test]
+    fn test_rejection_writer() {
+        let csv = "type, client, tx, amount\n\
+            withdrawal, 1, 1, 1.5\n\
+            dispute, 1, 2";
+        let mut writer = RejectionCsvWriter::new(Vec::new());
+        writer.write_header().unwrap();
+        let mut accounts = Accounts::default();
+        for tx in csv_transaction_reader(csv.as_bytes()) {
+            let tx = tx.unwrap();
+            let error = tx.execute_transaction(&mut accounts).unwrap_err();
+            writer.write_rejection(&tx, error).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "type,client,tx,amount,error\n\
+            withdrawal,1,1,1.5,insufficient_funds\n\
+            dispute,1,2,,transaction_not_found\n"
+        );
+    }
 }
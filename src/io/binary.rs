@@ -0,0 +1,340 @@
+//! A compact fixed-width binary transaction format for high-throughput pipelines.
+//!
+//! Every transaction is a record of `RECORD_SIZE` bytes, all integers little-endian:
+//!
+//! | offset | size | field                                                        |
+//! |--------|------|--------------------------------------------------------------|
+//! | 0      | 1    | transaction type, see `CsvTransactionType::to_byte`          |
+//! | 1      | 2    | client id                                                    |
+//! | 3      | 4    | transaction id                                               |
+//! | 7      | 8    | amount as a signed fixed-point number with 4 decimal places  |
+//!
+//! Transactions without an amount store `NO_AMOUNT`. Since records have a fixed width, a file can
+//! be memory mapped and decoded in place with `BinaryRecords`, skipping parsing entirely.
+use super::{CsvTransaction, CsvTransactionType, ReadError};
+use crate::account::Amount;
+use memmap2::Mmap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+
+/// Size in bytes of a single encoded transaction
+pub const RECORD_SIZE: usize = 15;
+
+/// Amounts are stored as integers in units of 1/AMOUNT_SCALE
+const AMOUNT_SCALE: f64 = 10_000.0;
+
+/// Amount value of transactions that carry no amount
+const NO_AMOUNT: i64 = i64::MIN;
+
+/// An error encountered while decoding or encoding a binary record.
+#[derive(Debug)]
+pub enum BinaryError {
+    UnknownTransactionType(u8),
+    /// The input ended in the middle of a record
+    TruncatedRecord,
+    /// The amount is not finite or too large for the fixed-point representation
+    UnrepresentableAmount(Amount),
+}
+
+impl Display for BinaryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BinaryError::UnknownTransactionType(t) => write!(f, "unknown transaction type {t}"),
+            BinaryError::TruncatedRecord => write!(f, "truncated record"),
+            BinaryError::UnrepresentableAmount(a) => write!(f, "unrepresentable amount {a}"),
+        }
+    }
+}
+
+impl CsvTransactionType {
+    fn to_byte(self) -> u8 {
+        match self {
+            CsvTransactionType::Deposit => 0,
+            CsvTransactionType::Withdrawal => 1,
+            CsvTransactionType::Dispute => 2,
+            CsvTransactionType::Resolve => 3,
+            CsvTransactionType::Chargeback => 4,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, BinaryError> {
+        match byte {
+            0 => Ok(CsvTransactionType::Deposit),
+            1 => Ok(CsvTransactionType::Withdrawal),
+            2 => Ok(CsvTransactionType::Dispute),
+            3 => Ok(CsvTransactionType::Resolve),
+            4 => Ok(CsvTransactionType::Chargeback),
+            _ => Err(BinaryError::UnknownTransactionType(byte)),
+        }
+    }
+}
+
+/// Encode a transaction into a single record.
+///
+/// # Errors
+/// - `UnrepresentableAmount` if the amount is not finite or out of range for the fixed-point
+///   representation
+pub fn encode(tx: &CsvTransaction) -> Result<[u8; RECORD_SIZE], BinaryError> {
+    let amount = match tx.amount {
+        None => NO_AMOUNT,
+        Some(amount) => {
+            let fixed = (amount as f64 * AMOUNT_SCALE).round();
+            // `NO_AMOUNT` is i64::MIN, so the representable range is symmetric around zero
+            if !fixed.is_finite() || fixed.abs() >= i64::MAX as f64 {
+                return Err(BinaryError::UnrepresentableAmount(amount));
+            }
+            fixed as i64
+        }
+    };
+    let mut record = [0; RECORD_SIZE];
+    record[0] = tx.tx_type.to_byte();
+    record[1..3].copy_from_slice(&tx.client.to_le_bytes());
+    record[3..7].copy_from_slice(&tx.tx.to_le_bytes());
+    record[7..15].copy_from_slice(&amount.to_le_bytes());
+    Ok(record)
+}
+
+/// Decode a single record. `record` must be exactly `RECORD_SIZE` bytes long.
+///
+/// # Errors
+/// - `UnknownTransactionType` if the type byte is not a known transaction type
+/// - `TruncatedRecord` if `record` has the wrong length
+pub fn decode(record: &[u8]) -> Result<CsvTransaction, BinaryError> {
+    let record: &[u8; RECORD_SIZE] = record
+        .try_into()
+        .map_err(|_| BinaryError::TruncatedRecord)?;
+    let amount = i64::from_le_bytes(record[7..15].try_into().unwrap());
+    Ok(CsvTransaction {
+        tx_type: CsvTransactionType::from_byte(record[0])?,
+        client: u16::from_le_bytes([record[1], record[2]]),
+        tx: u32::from_le_bytes(record[3..7].try_into().unwrap()),
+        amount: (amount != NO_AMOUNT).then(|| (amount as f64 / AMOUNT_SCALE) as Amount),
+    })
+}
+
+/// A streaming reader for the binary format, for input that can't be memory mapped, like pipes.
+pub struct BinaryTransactionReader<R> {
+    reader: BufReader<R>,
+    record: u64,
+}
+
+impl<R: Read> BinaryTransactionReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            record: 0,
+        }
+    }
+
+    /// The number of the record last read, starting at 1.
+    pub fn position(&self) -> u64 {
+        self.record
+    }
+}
+
+impl<R: Read> Iterator for BinaryTransactionReader<R> {
+    type Item = Result<CsvTransaction, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = [0; RECORD_SIZE];
+        let mut filled = 0;
+        // `read_exact` can't tell a clean end of input apart from a truncated record
+        while filled < RECORD_SIZE {
+            match self.reader.read(&mut buf[filled..]) {
+                Ok(0) if filled == 0 => return None,
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(ReadError::Io(e))),
+            }
+        }
+        self.record += 1;
+        Some(decode(&buf[..filled]).map_err(|kind| ReadError::Binary {
+            record: self.record,
+            kind,
+        }))
+    }
+}
+
+/// A zero-copy reader for the binary format, decoding records directly from a byte buffer, like a
+/// memory mapped file from `map_file`.
+pub struct BinaryRecords<B> {
+    bytes: B,
+    offset: usize,
+    record: u64,
+}
+
+impl<B: AsRef<[u8]>> BinaryRecords<B> {
+    pub fn new(bytes: B) -> Self {
+        Self {
+            bytes,
+            offset: 0,
+            record: 0,
+        }
+    }
+
+    /// The number of the record last read, starting at 1.
+    pub fn position(&self) -> u64 {
+        self.record
+    }
+}
+
+impl<B: AsRef<[u8]>> Iterator for BinaryRecords<B> {
+    type Item = Result<CsvTransaction, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.bytes.as_ref();
+        if self.offset >= bytes.len() {
+            return None;
+        }
+        // a trailing partial record is decoded as is, which reports it as truncated
+        let end = bytes.len().min(self.offset + RECORD_SIZE);
+        let record = &bytes[self.offset..end];
+        self.offset = end;
+        self.record += 1;
+        Some(decode(record).map_err(|kind| ReadError::Binary {
+            record: self.record,
+            kind,
+        }))
+    }
+}
+
+/// Memory map `path` for reading with `BinaryRecords`.
+pub fn map_file(path: &Path) -> std::io::Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: the mapping is read only. Modifying the file while it is mapped is undefined
+    // behavior, which we accept in the same way as any other tool reading its input this way.
+    unsafe { Mmap::map(&file) }
+}
+
+/// A writer for the binary format.
+pub struct BinaryTransactionWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> BinaryTransactionWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Write a single transaction.
+    ///
+    /// # Errors
+    /// An error of kind `InvalidData` if the transaction can't be encoded, see `encode`.
+    pub fn write_transaction(&mut self, tx: &CsvTransaction) -> std::io::Result<()> {
+        let record =
+            encode(tx).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        self.writer.write_all(&record)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Accounts;
+    use crate::io::csv_transaction_reader;
+
+    const CSV: &str = "type, client, tx, amount\n\
+        deposit, 1, 1, 1.25\n\
+        deposit, 2, 2, 2.0\n\
+        withdrawal, 1, 3, 0.25\n\
+        dispute, 2, 2\n";
+
+    fn encoded_csv() -> Vec<u8> {
+        let mut writer = BinaryTransactionWriter::new(Vec::new());
+        for tx in csv_transaction_reader(CSV.as_bytes()) {
+            writer.write_transaction(&tx.unwrap()).unwrap();
+        }
+        writer.writer
+    }
+
+    fn apply(txs: impl Iterator<Item = Result<CsvTransaction, ReadError>>) -> Accounts {
+        let mut accounts = Accounts::default();
+        for tx in txs {
+            tx.unwrap().execute_transaction(&mut accounts).unwrap();
+        }
+        accounts
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = encoded_csv();
+        assert_eq!(bytes.len(), 4 * RECORD_SIZE);
+        let tx = decode(&bytes[..RECORD_SIZE]).unwrap();
+        assert!(matches!(tx.tx_type, CsvTransactionType::Deposit));
+        assert_eq!((tx.client, tx.tx, tx.amount), (1, 1, Some(1.25)));
+        let tx = decode(&bytes[3 * RECORD_SIZE..]).unwrap();
+        assert!(matches!(tx.tx_type, CsvTransactionType::Dispute));
+        assert_eq!((tx.client, tx.tx, tx.amount), (2, 2, None));
+    }
+
+    #[test]
+    fn test_readers() {
+        let bytes = encoded_csv();
+        for mut accounts in [
+            apply(BinaryTransactionReader::new(bytes.as_slice())),
+            apply(BinaryRecords::new(bytes.as_slice())),
+        ] {
+            assert_eq!(accounts.client_account(1).available(), 1.0);
+            assert_eq!(accounts.client_account(2).held(), 2.0);
+        }
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let bytes = encoded_csv();
+        let bytes = &bytes[..bytes.len() - 1];
+        let mut records = BinaryRecords::new(bytes);
+        let mut reader = BinaryTransactionReader::new(bytes);
+        for _ in 0..3 {
+            assert!(records.next().unwrap().is_ok());
+            assert!(reader.next().unwrap().is_ok());
+        }
+        for last in [records.next(), reader.next()] {
+            assert!(matches!(
+                last,
+                Some(Err(ReadError::Binary {
+                    record: 4,
+                    kind: BinaryError::TruncatedRecord
+                }))
+            ));
+        }
+        assert!(records.next().is_none());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_invalid_records() {
+        let mut record = encode(&CsvTransaction {
+            tx_type: CsvTransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(1.0),
+        })
+        .unwrap();
+        record[0] = 9;
+        assert!(matches!(
+            decode(&record),
+            Err(BinaryError::UnknownTransactionType(9))
+        ));
+        assert!(matches!(
+            encode(&CsvTransaction {
+                tx_type: CsvTransactionType::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(Amount::NAN),
+            }),
+            Err(BinaryError::UnrepresentableAmount(_))
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+use flate2::bufread::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The compression formats supported for input and output files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Guess the compression from the extension of `path`, e.g. `transactions.csv.gz`. Returns
+    /// `None` if the extension isn't a known compression format.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Detect the compression of a stream from its magic bytes, without consuming any input.
+    pub fn detect(reader: &mut impl BufRead) -> std::io::Result<Self> {
+        let head = reader.fill_buf()?;
+        Ok(if head.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if head.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!(
+                "unknown compression '{s}', expected one of: none, gzip, zstd"
+            )),
+        }
+    }
+}
+
+/// Wrap `reader` in a streaming decompressor for the compression detected from its magic bytes.
+/// Uncompressed input is passed through as is.
+pub fn decompress<R: BufRead + 'static>(mut reader: R) -> std::io::Result<Box<dyn Read>> {
+    Ok(match Compression::detect(&mut reader)? {
+        Compression::None => Box::new(reader),
+        // files produced by e.g. `pigz` or concatenated with `cat` consist of several members
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+    })
+}
+
+/// A writer that compresses everything written to it. Compressed streams end with a trailer, so
+/// `finish` must be called once all output is written.
+pub enum CompressedWriter<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn new(compression: Compression, writer: W) -> std::io::Result<Self> {
+        Ok(match compression {
+            Compression::None => CompressedWriter::None(writer),
+            Compression::Gzip => {
+                CompressedWriter::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+            }
+            Compression::Zstd => CompressedWriter::Zstd(zstd::Encoder::new(writer, 0)?),
+        })
+    }
+
+    /// Write the trailer of the compressed stream, if any, and return the underlying writer.
+    pub fn finish(self) -> std::io::Result<W> {
+        let mut writer = match self {
+            CompressedWriter::None(writer) => writer,
+            CompressedWriter::Gzip(encoder) => encoder.finish()?,
+            CompressedWriter::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            CompressedWriter::None(writer) => writer.write(buf),
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+            CompressedWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            CompressedWriter::None(writer) => writer.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+            CompressedWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(compression: Compression) -> (Compression, String) {
+        let mut writer = CompressedWriter::new(compression, Vec::new()).unwrap();
+        writer.write_all(b"type, client, tx, amount\n").unwrap();
+        let compressed = writer.finish().unwrap();
+        let detected = Compression::detect(&mut compressed.as_slice()).unwrap();
+        let mut decompressed = String::new();
+        decompress(std::io::Cursor::new(compressed))
+            .unwrap()
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        (detected, decompressed)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            assert_eq!(
+                round_trip(compression),
+                (compression, "type, client, tx, amount\n".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_path() {
+        let path = Path::new("in.csv.gz");
+        assert_eq!(Compression::from_path(path), Some(Compression::Gzip));
+        let path = Path::new("in.jsonl.ZST");
+        assert_eq!(Compression::from_path(path), Some(Compression::Zstd));
+        assert_eq!(Compression::from_path(Path::new("in.csv")), None);
+    }
+}
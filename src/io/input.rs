@@ -0,0 +1,244 @@
+use super::binary::{BinaryRecords, map_file};
+use super::compression::{Compression, decompress};
+use super::{CsvTransaction, Format, ReadError, TransactionReader};
+use memmap2::Mmap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+
+/// A source of transactions given on the command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    /// Create an input from a command line argument, where `-` means stdin.
+    pub fn from_arg(path: PathBuf) -> Self {
+        if path.as_os_str() == "-" {
+            Input::Stdin
+        } else {
+            Input::File(path)
+        }
+    }
+
+    /// Create the inputs for a list of command line arguments. Directories are replaced by the
+    /// files they contain, ordered by file name. Hidden files and subdirectories are skipped.
+    pub fn expand(paths: impl IntoIterator<Item = PathBuf>) -> std::io::Result<Vec<Self>> {
+        let mut inputs = Vec::new();
+        for path in paths {
+            if !path.is_dir() {
+                inputs.push(Input::from_arg(path));
+                continue;
+            }
+            let mut files = Vec::new();
+            for entry in path.read_dir()? {
+                let entry = entry?;
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if !hidden && entry.path().is_file() {
+                    files.push(entry.path());
+                }
+            }
+            files.sort();
+            inputs.extend(files.into_iter().map(Input::File));
+        }
+        Ok(inputs)
+    }
+
+    /// Open the input for reading. Compressed input is decompressed, and uncompressed binary files
+    /// are memory mapped. Without an explicit `format`, it is guessed from the file extension,
+    /// falling back to csv.
+    fn open(&self, format: Option<Format>) -> std::io::Result<InputReader> {
+        match self {
+            Input::Stdin => {
+                let reader = decompress(std::io::stdin().lock())?;
+                let format = format.unwrap_or(Format::Csv);
+                Ok(InputReader::Stream(TransactionReader::new(format, reader)))
+            }
+            Input::File(path) => {
+                let format = format
+                    .or_else(|| Format::from_path(path))
+                    .unwrap_or(Format::Csv);
+                let mut reader = BufReader::new(File::open(path)?);
+                if format == Format::Binary
+                    && Compression::detect(&mut reader)? == Compression::None
+                {
+                    return Ok(InputReader::Mapped(BinaryRecords::new(map_file(path)?)));
+                }
+                let reader = decompress(reader)?;
+                Ok(InputReader::Stream(TransactionReader::new(format, reader)))
+            }
+        }
+    }
+}
+
+impl Display for Input {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Input::Stdin => write!(f, "<stdin>"),
+            Input::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+enum InputReader {
+    Stream(TransactionReader<Box<dyn Read>>),
+    Mapped(BinaryRecords<Mmap>),
+}
+
+impl InputReader {
+    fn position(&self) -> u64 {
+        match self {
+            InputReader::Stream(reader) => reader.position(),
+            InputReader::Mapped(reader) => reader.position(),
+        }
+    }
+}
+
+impl Iterator for InputReader {
+    type Item = Result<CsvTransaction, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            InputReader::Stream(reader) => reader.next(),
+            InputReader::Mapped(reader) => reader.next(),
+        }
+    }
+}
+
+/// Reads the transactions of several inputs as one stream, by concatenating the inputs in the
+/// order they are given. Inputs are opened one at a time, when the previous one is exhausted.
+///
+/// All transactions of an input are read before any transaction of the next input, so for every
+/// client, transactions are applied in input order first and in line order within an input second.
+/// A dispute in a later file can therefore refer to a deposit in an earlier file, but not the
+/// other way around.
+///
+/// Errors are reported with the input they occurred in. An input that can't be opened yields a
+/// single error, after which reading continues with the next input.
+pub struct MultiInputReader {
+    inputs: std::vec::IntoIter<Input>,
+    format: Option<Format>,
+    current: Option<(Input, InputReader)>,
+}
+
+impl MultiInputReader {
+    /// Create a reader for `inputs`. Every input is read in `format`, or in the format guessed
+    /// from its file extension if `format` is `None`.
+    pub fn new(inputs: Vec<Input>, format: Option<Format>) -> Self {
+        Self {
+            inputs: inputs.into_iter(),
+            format,
+            current: None,
+        }
+    }
+
+    /// The input of the transaction last read, with its line in that input, or its record number
+    /// for the binary format.
+    pub fn position(&self) -> Option<(&Input, u64)> {
+        let (input, reader) = self.current.as_ref()?;
+        Some((input, reader.position()))
+    }
+}
+
+impl Iterator for MultiInputReader {
+    type Item = Result<CsvTransaction, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((input, reader)) = &mut self.current {
+                match reader.next() {
+                    Some(Ok(tx)) => return Some(Ok(tx)),
+                    Some(Err(e)) => return Some(Err(ReadError::in_input(input, e))),
+                    None => self.current = None,
+                }
+            }
+            let input = self.inputs.next()?;
+            match input.open(self.format) {
+                Ok(reader) => self.current = Some((input, reader)),
+                Err(e) => return Some(Err(ReadError::in_input(&input, ReadError::Io(e)))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Accounts;
+    use std::path::Path;
+
+    /// Create an empty scratch directory for a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ktht-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = test_dir("expand");
+        write(&dir.join("2024-01-02.csv"), "");
+        write(&dir.join("2024-01-01.csv"), "");
+        write(&dir.join(".hidden.csv"), "");
+        std::fs::create_dir(dir.join("archive")).unwrap();
+        let inputs = Input::expand([PathBuf::from("-"), dir.clone(), PathBuf::from("x.csv")]);
+        assert_eq!(
+            inputs.unwrap(),
+            [
+                Input::Stdin,
+                Input::File(dir.join("2024-01-01.csv")),
+                Input::File(dir.join("2024-01-02.csv")),
+                Input::File(PathBuf::from("x.csv")),
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_concatenated_inputs() {
+        let dir = test_dir("concat");
+        write(
+            &dir.join("1.csv"),
+            "type, client, tx, amount\ndeposit, 1, 1, 5.0\n",
+        );
+        write(
+            &dir.join("2.jsonl"),
+            "{\"type\": \"dispute\", \"client\": 1, \"tx\": 1}\n",
+        );
+        let inputs = vec![
+            Input::File(dir.join("1.csv")),
+            Input::File(dir.join("2.jsonl")),
+        ];
+        let mut accounts = Accounts::default();
+        for tx in MultiInputReader::new(inputs, None) {
+            tx.unwrap().execute_transaction(&mut accounts).unwrap();
+        }
+        assert_eq!(accounts.client_account(1).held(), 5.0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_errors_name_input() {
+        let dir = test_dir("errors");
+        write(&dir.join("bad.jsonl"), "{}\n");
+        let inputs = vec![
+            Input::File(dir.join("missing.csv")),
+            Input::File(dir.join("bad.jsonl")),
+        ];
+        let errors = MultiInputReader::new(inputs, None)
+            .map(|tx| tx.err().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with(&dir.join("missing.csv").display().to_string()));
+        assert!(errors[1].starts_with(&format!("{}: line 1", dir.join("bad.jsonl").display())));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,10 @@
+//! A toy payments engine applying streams of transactions to client accounts on a sharded
+//! thread-per-core runtime, see `rt`. The `ktht` binary is a command line front end to it, see
+//! `cli` and `cmd`.
+
+pub mod account;
+pub mod cli;
+pub mod cmd;
+pub mod io;
+pub mod rt;
+pub mod server;
@@ -1,17 +1,46 @@
+use fnv::FnvHashMap;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::convert::{Infallible, identity};
+use std::fmt::{Display, Formatter};
 use std::marker::PhantomData;
-use std::sync::mpsc::Sender;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError, channel};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
 use std::thread::{JoinHandle, spawn};
+use std::time::Instant;
+
+pub mod affinity;
+pub mod assign;
+pub mod deterministic;
+pub mod fallible;
+pub mod metrics;
+pub mod numa;
+pub mod outcome;
+pub mod rebalance;
+
+use affinity::Placement;
+use assign::ShardAssigner;
+use fallible::{ErrorStream, OnError};
+use metrics::{ShardMetrics, ShardStats};
+use outcome::{Outcome, Quarantine, Quarantined, ShardFailure, panic_message};
+use rebalance::{Migrate, Migration, RebalancePolicy};
+
+/// The number of items between two checks for imbalanced shards in `try_fold_rebalanced`.
+const REBALANCE_EVERY: u64 = 4096;
 
 /// This implements a toy share nothing/thread per core sharded execution strategy where items of
 /// type `T` are submitted to a thread pool for processing. The shard selection is defined by the
-/// `Shardable` trait, which submitted items must implement. All shards have an instance of type `S`
-/// which is the mutable state of the shard.
+/// key of the `Shardable` trait, which submitted items must implement, and a `ShardAssigner`
+/// mapping keys to shards, see `assign`. All shards have an instance of type `S` which is the
+/// mutable state of the shard.
 ///
 /// For this exercise, the Client ID is used for shard selection, and state `S` is an instance of
 /// `crate::account::Accounts`
 ///
 /// This strategy allows the dataset to be processed in parallel, while items per client are
-/// processed in order, all while remaining lock-free and with minimal context switching.
+/// processed in order, all while remaining lock-free and with minimal context switching. Only a
+/// runtime which can move keys between shards, see `new_migratable`, takes a read lock per item.
 ///
 /// My assumption is that the number of clients is high, and the number of transactions is high,
 /// and because of this the shards should get a similar number of tasks. On smaller workloads
@@ -20,34 +49,257 @@ use std::thread::{JoinHandle, spawn};
 /// This implementation does not handle back pressure. It could see queues for one shard be
 /// significantly longer than queues for other shards if the number of transactions per client is
 /// statistically uneven, or all queues fill up if reading is faster than processing. There are
-/// multiple strategies to handle this, which could be a topic for discussion.
+/// multiple strategies to handle this, which could be a topic for discussion. Uneven queues can be
+/// evened out by moving keys to other shards at runtime, see `rebalance`.
+///
+/// A panic while processing an item doesn't take the runtime down: the item is quarantined and
+/// the shard moves on, see `outcome`.
+///
+/// A batch of items is processed with `try_fold`. A long-lived runtime, like the one behind
+/// `crate::server`, is created with `new`, fed with `process_item` from any number of threads, and
+/// stopped with `finish`. While it runs, the state of the shards can be read or changed with `call`
+/// and `snapshot`, and its load can be observed with `stats`, see `metrics`.
 ///
 /// # Types
 /// - `T` is the type that will be submitted for processing
 /// - `F` is a function of type (&mut S, T) which is run on the thread pool to fold `T` into `S`
 /// - `S` is the mutable state of a shard
-pub struct ShardedThreadPerCoreRuntime<T, F, S> {
-    shards: Vec<(Sender<T>, JoinHandle<S>)>,
+pub struct ShardedThreadPerCoreRuntime<T: Quarantine, F, S> {
+    shards: Vec<Shard<T, S>>,
+    assigner: Box<dyn ShardAssigner>,
+    // the keys moved away from the shard of the assigner by `migrate`, if the runtime was created
+    // with `new_migratable`; otherwise items are routed by the assigner alone, without a lock
+    routes: Option<Routes<T, S>>,
     _t: PhantomData<T>,
     _f: PhantomData<F>,
     _s: PhantomData<S>,
 }
 
-/// Allows a type to select which shard it should be submitted to.
+/// The keys moved between shards by `migrate`, and how to move their state
+struct Routes<T: Quarantine, S> {
+    moved: RwLock<FnvHashMap<u64, Route<T, S>>>,
+    take: Take<S>,
+}
+
+/// Takes the state of a key out of a shard state, as a call putting it into the state of another
+/// shard. Only `new_migratable` needs `S: Migrate` to create it, so the other functions moving
+/// keys work with any `S`.
+type Take<S> = fn(&mut S, u64) -> Option<Call<S>>;
+
+fn take<S: Migrate + 'static>(state: &mut S, key: u64) -> Option<Call<S>> {
+    let part = state.take(key)?;
+    Some(Box::new(move |state: &mut S| state.put(key, part)))
+}
+
+/// Where a key moved by `migrate` is routed
+enum Route<T: Quarantine, S> {
+    /// The key was moved to this shard.
+    Moved(usize),
+    /// The key is moving to shard `to`, and its messages are held back until its state was taken
+    /// from the old shard.
+    Migrating {
+        to: usize,
+        held: Mutex<Vec<Message<T, S>>>,
+    },
+}
+
+/// A migration waiting for the old shard to take out the state of the key, see `Take`
+struct PendingMigration<S> {
+    migration: Migration,
+    put: Receiver<Option<Call<S>>>,
+}
+
+/// A shard thread, with the channel to it, the handle returning whether it failed and its
+/// quarantined items, and its metrics
+struct Shard<T: Quarantine, S> {
+    tx: Sender<Message<T, S>>,
+    join_handle: JoinHandle<ShardResult<T::Record>>,
+    metrics: Arc<ShardMetrics>,
+}
+
+/// Whether a shard thread handed its final state to `Message::Finish`, or the message of the
+/// panic that failed it, along with the items it quarantined
+type ShardResult<R> = (Result<(), String>, Vec<Quarantined<R>>);
+
+/// A function run on the state of a shard, see `Message::Call`
+type Call<S> = Box<dyn FnOnce(&mut S) + Send>;
+
+/// A message to a shard thread.
+enum Message<T, S> {
+    /// An item to fold into the state of the shard
+    Item(T),
+    /// A function to run on the state of the shard, which sends its result back itself
+    Call(Call<S>),
+    /// A function consuming the final state of the shard, which sends its result back itself. It
+    /// is the last message of the shard, see `ShardedThreadPerCoreRuntime::finish_with`.
+    Finish(Box<dyn FnOnce(S) + Send>),
+}
+
+/// Why a `ShardedThreadPerCoreRuntime` could not be started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// Zero shards were requested
+    NoShards,
+    /// The cores of the machine could not be enumerated, or none are left for the shards, see
+    /// `affinity::Placement::shard_cores`
+    NoCores,
+    /// A core that is not available on this machine was requested
+    UnavailableCore(usize),
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::NoShards => write!(f, "the runtime needs at least one shard"),
+            RuntimeError::NoCores => write!(f, "could not enumerate the cores of this machine"),
+            RuntimeError::UnavailableCore(core) => {
+                write!(f, "core {core} is not available on this machine")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Allows a type to select which shard it should be submitted to. Items with the same key are
+/// processed by the same shard, in order.
 pub trait Shardable {
-    fn shard_id(&self, num_shards: u8) -> usize;
+    fn shard_key(&self) -> u64;
+}
+
+/// The loop of a shard thread, folding the items it receives into its state until the runtime is
+/// finished, which hands the state to `Message::Finish`.
+///
+/// A panicking item is quarantined, and the panic of a `call` or `snapshot` is returned to the
+/// caller, see `outcome`. Any other panicking call, which only happens while moving a key, fails
+/// the shard: its state is dropped, and the items it receives afterwards are quarantined, until
+/// the runtime is finished.
+fn run_shard<T, F, S>(
+    shard_id: usize,
+    rx: Receiver<Message<T, S>>,
+    f: F,
+    metrics: &ShardMetrics,
+) -> ShardResult<T::Record>
+where
+    T: Shardable + Quarantine,
+    F: Fn(&mut S, T),
+    S: Default,
+{
+    // the state is created on the shard thread, once it is pinned, so its memory is first touched
+    // on the NUMA node of the shard, see `numa`
+    let mut state = S::default();
+    let mut quarantined = Vec::new();
+    let mut idle_since = Instant::now();
+    while let Ok(message) = rx.recv() {
+        let received = Instant::now();
+        metrics.received(received - idle_since);
+        match message {
+            Message::Item(item) => {
+                idle_since = fold_item(shard_id, &mut state, &f, item, metrics, &mut quarantined);
+            }
+            Message::Call(call) => {
+                if let Err(panic) = catch_unwind(AssertUnwindSafe(|| call(&mut state))) {
+                    metrics.failed();
+                    drop(state);
+                    return fail_shard(shard_id, rx, panic_message(panic), quarantined, metrics);
+                }
+                idle_since = Instant::now();
+                metrics.busy(idle_since - received);
+            }
+            Message::Finish(finish) => {
+                let finished = catch_unwind(AssertUnwindSafe(|| finish(state)));
+                metrics.busy(received.elapsed());
+                return (finished.map_err(panic_message), quarantined);
+            }
+        }
+    }
+    // the runtime was dropped without finishing it
+    (Ok(()), quarantined)
+}
+
+/// Wait for the result of a call queued on shard `shard_id` by `call` or `snapshot`.
+fn call_result<R>(
+    shard_id: usize,
+    result: &Receiver<std::thread::Result<R>>,
+) -> Result<R, ShardFailure> {
+    match result.recv() {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(panic)) => Err(ShardFailure {
+            shard: shard_id,
+            message: panic_message(panic),
+        }),
+        Err(_) => Err(ShardFailure::dropped_call(shard_id)),
+    }
+}
+
+/// Fold an item into the state of a shard, quarantining it if `f` panics, and return when it was
+/// done.
+fn fold_item<T, F, S>(
+    shard_id: usize,
+    state: &mut S,
+    f: &F,
+    item: T,
+    metrics: &ShardMetrics,
+    quarantined: &mut Vec<Quarantined<T::Record>>,
+) -> Instant
+where
+    T: Shardable + Quarantine,
+    F: Fn(&mut S, T),
+{
+    let received = Instant::now();
+    let key = item.shard_key();
+    let record = item.record();
+    // the state is left as the panic left it, see `outcome`
+    if let Err(panic) = catch_unwind(AssertUnwindSafe(|| f(state, item))) {
+        metrics.panicked();
+        quarantined.push(Quarantined {
+            shard: shard_id,
+            item: record,
+            message: panic_message(panic),
+        });
+    }
+    let done = Instant::now();
+    metrics.processed_item(key, done - received);
+    done
+}
+
+/// Quarantine the items a failed shard receives until the runtime is finished. Calls are dropped
+/// without running, so `call` and `snapshot` return `ShardFailure::dropped_call`.
+fn fail_shard<T: Quarantine, S>(
+    shard_id: usize,
+    rx: Receiver<Message<T, S>>,
+    message: String,
+    mut quarantined: Vec<Quarantined<T::Record>>,
+    metrics: &ShardMetrics,
+) -> ShardResult<T::Record> {
+    let mut idle_since = Instant::now();
+    while let Ok(received) = rx.recv() {
+        let now = Instant::now();
+        metrics.received(now - idle_since);
+        idle_since = now;
+        if let Message::Item(item) = received {
+            quarantined.push(Quarantined {
+                shard: shard_id,
+                item: item.record(),
+                message: "the shard failed".to_string(),
+            });
+        }
+    }
+    (Err(message), quarantined)
 }
 
 impl<T, F, S> ShardedThreadPerCoreRuntime<T, F, S>
 where
-    T: Send + Shardable + 'static,
+    T: Send + Shardable + Quarantine + 'static,
     F: Fn(&mut S, T) + Clone + Send + 'static,
     S: Default + Send + 'static,
 {
-    /// ```rust
     /// # Parameters
-    /// - `max_threads`: The maximum number of worker threads to spawn. Each thread will be pinned to a different CPU core.
-    ///   The system will never spawn more threads than the number of available cores.
+    /// - `placement`: The maximum number of worker threads to spawn, and the cores to run them on, see `affinity::Placement`.
+    ///   Each thread will be pinned to a different CPU core, unless pinning is disabled. The system will never spawn more
+    ///   threads than the number of cores it may use. If the placement has a reader core, the calling thread is pinned to
+    ///   it once the shards are started, so they don't inherit its affinity.
+    /// - `assigner`: The strategy routing the key of every item to a shard, e.g. `assign::Modulo`.
     /// - `func`: A closure or function that takes mutable access to a state object of type `S` and processes an
     ///   incoming item. This function is invoked for each item received in the thread's input queue.
     ///
@@ -57,12 +309,13 @@ where
     ///   trait for initialization.
     ///
     /// # Returns
-    /// An instance of the struct containing worker threads, each associated with:
+    /// An instance of the struct containing worker threads, or a `RuntimeError` if the placement has no threads,
+    /// or its cores can't be used. Each worker thread is associated with:
     /// - A transmission channel to send tasks into the thread.
     /// - A join handle that allows retrieving the final state produced by the thread once it exits.
     ///
     /// # Implementation Details
-    /// - The method determines the available CPU cores using `core_affinity::get_core_ids()` and assigns threads
+    /// - The method determines the cores of the shards with `affinity::Placement::shard_cores` and assigns threads
     ///   to specific cores using `core_affinity::set_for_current(core_id)`. This ensures better cache locality and
     ///   reduces thread contention.
     /// - A `Vec` is used to store the tuple `(tx, join_handle)` for each worker thread:
@@ -70,158 +323,1042 @@ where
     ///   - `join_handle`: A `JoinHandle` for the thread, which can be used to wait for its completion or retrieve
     ///     its final state.
     /// - Each worker thread initializes its own state object using `S::default()`, and processes tasks by receiving
-    ///   items from the channel and passing them to `func`.
+    ///   items from the channel and passing them to `func`. If `func` panics, the panic is caught and a record of
+    ///   the item is quarantined, see `outcome::Quarantine`.
     /// - When the channel closes, the thread exits, and its final state is returned (if joined).
-    ///
-    /// # Panics
-    /// - The function panics if `core_affinity::get_core_ids()` fails to enumerate CPU cores.
-    ///
-    /// ```
-    fn new(max_threads: u8, func: F) -> Self {
-        let mut shards = Vec::with_capacity(max_threads as usize);
-        // enumerate available cores
-        for core_id in core_affinity::get_core_ids()
-            .expect("Could not enumerate cores")
-            .into_iter()
-            .take(max_threads as usize)
-        {
+    pub fn new(
+        placement: impl Into<Placement>,
+        assigner: Box<dyn ShardAssigner>,
+        func: F,
+    ) -> Result<Self, RuntimeError> {
+        Self::start(placement.into(), assigner, func, None)
+    }
+
+    fn start(
+        placement: Placement,
+        assigner: Box<dyn ShardAssigner>,
+        func: F,
+        take: Option<Take<S>>,
+    ) -> Result<Self, RuntimeError> {
+        let cores = placement.shard_cores()?;
+        let topology = &placement.topology;
+        // the items are submitted by the reader, and the threads it starts
+        let reader_node = placement
+            .reader_core
+            .and_then(|core| topology.node_of(core));
+        let mut shards = Vec::with_capacity(cores.ids().len());
+        for (shard_id, &core) in cores.ids().iter().enumerate() {
             let f = func.clone();
             // spsc would be better here, but let's keep our dependencies simple for this exercise
-            let (tx, rx) = std::sync::mpsc::channel();
+            let (tx, rx) = std::sync::mpsc::channel::<Message<T, S>>();
+            // an unpinned shard may run on any node
+            let node = topology.node_of(core).filter(|_| placement.pin);
+            let metrics = Arc::new(ShardMetrics::new(node, reader_node));
+            let shard_metrics = metrics.clone();
+            let pin = placement.pin;
             let join_handle = spawn(move || {
                 // lock the thread to a specific core
-                core_affinity::set_for_current(core_id);
-                let mut state = S::default();
-                while let Ok(item) = rx.recv() {
-                    f(&mut state, item);
+                if pin {
+                    affinity::pin_current_thread(core);
                 }
-                state
+                run_shard(shard_id, rx, f, &shard_metrics)
+            });
+            shards.push(Shard {
+                tx,
+                join_handle,
+                metrics,
             });
-            shards.push((tx, join_handle));
         }
-        shards.shrink_to_fit();
-        Self {
+        if let Some(core) = placement.reader_core {
+            affinity::pin_current_thread(core);
+        }
+        Ok(Self {
             shards,
+            assigner,
+            routes: take.map(|take| Routes {
+                moved: RwLock::default(),
+                take,
+            }),
             _t: PhantomData,
             _f: PhantomData,
             _s: PhantomData,
-        }
+        })
     }
 
-    /// ```rust
     /// Processes an item by determining its shard and sending it to the appropriate thread pool.
     ///
     /// # Parameters
     /// - `item: T` - The item to be processed, where `T` must implement `Shardable`.
     ///
-    /// # Panics
-    /// - This function will panic if:
-    ///     - The `send` operation on the transmitter fails. This indicates a bug in the
-    ///       program because the shard's thread pool should always be able to accept
-    ///       new items unless an unexpected condition occurs.
+    /// If the shard failed, a record of the item is quarantined instead, see `outcome`.
+    pub fn process_item(&self, item: T) {
+        self.send_by_key(item.shard_key(), Message::Item(item));
+    }
+
+    /// Send `message` to the shard of `key`, and return the id of that shard.
+    fn send_by_key(&self, key: u64, message: Message<T, S>) -> usize {
+        let Some(routes) = &self.routes else {
+            let shard_id = self.assigner.assign(key, self.shards.len());
+            self.send(shard_id, message);
+            return shard_id;
+        };
+        // hold the routing table until the message is queued, so the key can't move meanwhile
+        let routes = routes.moved.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(Route::Migrating { to, held }) = routes.get(&key) {
+            held.lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(message);
+            return *to;
+        }
+        let shard_id = self.shard_id(&routes, key);
+        self.send(shard_id, message);
+        shard_id
+    }
+
+    /// The shard of `key`, or the shard it is moving to
+    fn shard_id(&self, routes: &FnvHashMap<u64, Route<T, S>>, key: u64) -> usize {
+        match routes.get(&key) {
+            Some(&Route::Moved(shard_id) | &Route::Migrating { to: shard_id, .. }) => shard_id,
+            None => self.assigner.assign(key, self.shards.len()),
+        }
+    }
+
+    fn send(&self, shard_id: usize, message: Message<T, S>) {
+        let shard = &self.shards[shard_id];
+        shard.metrics.submitted();
+        // a failed shard keeps receiving, so the thread only hangs up if it panicked outside of
+        // `func` and the calls, which `finish` reports
+        let _ = shard.tx.send(message);
+    }
+
+    /// The current metrics of every shard, ordered by shard id. The shards keep running, so the
+    /// metrics may change right after.
+    pub fn stats(&self) -> Vec<ShardStats> {
+        self.shards
+            .iter()
+            .map(|shard| shard.metrics.stats())
+            .collect()
+    }
+
+    /// Run `func` on the state of the shard that `key` is routed to, like an item with the same
+    /// shard key would be, and wait for its result.
+    ///
+    /// The call is queued behind the items already submitted to the shard, so it sees the effect
+    /// of every item of the same key submitted before it by the calling thread.
     ///
-    /// ```
-    fn process_item(&self, item: T) {
-        let shard_id = item.shard_id(self.shards.len() as u8);
-        let (tx, _) = &self.shards[shard_id];
-        tx.send(item).expect("Could not submit item to thread pool"); // this would be a bug
+    /// # Errors
+    /// If the shard failed, or `func` panicked, which leaves the state as the panic left it, like
+    /// a panicking item, see `outcome`.
+    pub fn call<K, R>(
+        &self,
+        key: &K,
+        func: impl FnOnce(&mut S) -> R + Send + 'static,
+    ) -> Result<R, ShardFailure>
+    where
+        K: Shardable,
+        R: Send + 'static,
+    {
+        let (reply, result) = channel();
+        let call = move |state: &mut S| {
+            // the caller is blocked waiting for the result, so this can't fail
+            let _ = reply.send(catch_unwind(AssertUnwindSafe(|| func(state))));
+        };
+        let shard_id = self.send_by_key(key.shard_key(), Message::Call(Box::new(call)));
+        call_result(shard_id, &result)
+    }
+
+    /// Run `func` on the state of every shard and return the results, ordered by shard id.
+    ///
+    /// This acts as a barrier: a marker is queued on every shard before waiting for any of them,
+    /// and every shard runs `func` when it reaches its marker. The results therefore reflect all
+    /// items submitted before the call, and none submitted after it returns, on every shard. Items
+    /// submitted concurrently by other threads may be included on some shards and not on others,
+    /// which is consistent as long as items of different shards are independent, like the accounts
+    /// of different clients.
+    ///
+    /// The result of a shard is an error if the shard failed, or `func` panicked on it, see
+    /// `call`.
+    pub fn snapshot<R>(
+        &self,
+        func: impl Fn(&S) -> R + Clone + Send + 'static,
+    ) -> Vec<Result<R, ShardFailure>>
+    where
+        R: Send + 'static,
+    {
+        let pending = (0..self.shards.len())
+            .map(|shard_id| {
+                let (reply, result) = channel();
+                let func = func.clone();
+                let call = move |state: &mut S| {
+                    let _ = reply.send(catch_unwind(AssertUnwindSafe(|| func(state))));
+                };
+                self.send(shard_id, Message::Call(Box::new(call)));
+                result
+            })
+            .collect::<Vec<_>>();
+        pending
+            .into_iter()
+            .enumerate()
+            .map(|(shard_id, result)| call_result(shard_id, &result))
+            .collect()
     }
 
-    /// ```rust
     /// Finalizes the current operation and collects the results from all shards.
     ///
     /// This method processes each shard by performing the following steps:
-    /// 1. Drops the sender (`tx`) associated with each shard. This ensures that the receiver (`recv`)
+    /// 1. Sends a last message to each shard, handing its final state back once the items queued before are processed.
+    /// 2. Drops the sender (`tx`) associated with each shard. This ensures that the receiver (`recv`)
     ///    of the corresponding shard will return an error, signaling the thread to exit its processing loop.
-    /// 2. Joins the thread handle (`join_handle`) associated with each shard to retrieve the final state
-    ///    produced by that shard's thread. If the thread panics during execution, this method will
-    ///    panic as well, as this indicates a bug in the threading logic or shard processing.
+    /// 3. Joins the thread handle (`join_handle`) associated with each shard to wait for that shard's thread, and
+    ///    receives its final state. If the thread panicked, the shard is reported as failed instead.
     ///
     /// # Returns
-    /// A `Vec<S>` containing the final states of all shards after their respective threads have completed
-    /// execution.
-    ///
-    /// ```
-    fn finish(self) -> Vec<S> {
-        let mut result = Vec::with_capacity(self.shards.len());
-        for (tx, join_handle) in self.shards {
+    /// An `Outcome` containing the final states of all shards after their respective threads have completed
+    /// execution, their metrics, and the items that were quarantined.
+    pub fn finish(self) -> Outcome<S, T::Record> {
+        self.finish_with(identity)
+    }
+
+    /// Like `finish`, but every shard turns its final state into `func(state)` on its own thread
+    /// before exiting, so the shards do it in parallel, e.g. to sort their state. If `func`
+    /// panics, the shard is reported as failed.
+    pub fn finish_with<R: Send + 'static>(
+        self,
+        func: impl Fn(S) -> R + Clone + Send + 'static,
+    ) -> Outcome<R, T::Record> {
+        let mut outcome = Outcome {
+            shards: Vec::with_capacity(self.shards.len()),
+            stats: Vec::with_capacity(self.shards.len()),
+            quarantined: Vec::new(),
+            migrations: Vec::new(),
+            aborted: false,
+        };
+        let results = self
+            .shards
+            .iter()
+            .map(|shard| {
+                let (reply, result) = channel();
+                let func = func.clone();
+                let finish = move |state| {
+                    let _ = reply.send(func(state));
+                };
+                // a failed shard drops the message, and `join` below reports why it failed
+                let _ = shard.tx.send(Message::Finish(Box::new(finish)));
+                result
+            })
+            .collect::<Vec<_>>();
+        for ((shard_id, shard), result) in self.shards.into_iter().enumerate().zip(results) {
             // after dropping the sender, the recv method of `Receiver` will return an error, which
-            // in turn will cause the shard thread to exit its loop and return the shard state. which
-            // is collected via `JoinHandle::join` below.
-            drop(tx);
-            result.push(join_handle.join().expect("Thread panicked")); // this would be a bug
+            // in turn will cause the shard thread to exit its loop, unless it exited on the
+            // message above, which is collected via `JoinHandle::join` below.
+            drop(shard.tx);
+            let (finished, quarantined) = shard
+                .join_handle
+                .join()
+                .unwrap_or_else(|panic| (Err(panic_message(panic)), Vec::new()));
+            let state = finished.and_then(|()| {
+                result
+                    .recv()
+                    .map_err(|_| "the shard exited without its state".to_string())
+            });
+            let failure = |message| ShardFailure {
+                shard: shard_id,
+                message,
+            };
+            outcome.shards.push(state.map_err(failure));
+            outcome.quarantined.extend(quarantined);
+            outcome.stats.push(shard.metrics.stats());
         }
-        result
+        outcome
     }
 
-    /// ```
     /// Consumes an iterator over `Result<T, E>` items and processes them in parallel using the
     /// specified number of worker threads by applying function `func` to each item.
     ///
     /// # Parameters
-    /// - `parallelism`: The level of parallelism, specified as the number of concurrent workers to process items.
+    /// - `placement`: The level of parallelism, specified as the number of concurrent workers to process items, or
+    ///   where to run them, see `new`.
+    /// - `assigner`: The strategy routing items to shards, see `new`.
     /// - `func`: A closure or function that takes an input of type `T` and produces a transformed output of type `S`.
+    /// - `finish`: A closure or function turning the final state of a shard into its result of type `R`, on the
+    ///   shard, see `finish_with`.
     /// - `items`: An iterator over `Result<T, E>` items, where `T` is the input type and `E` is the error type.
     ///
     /// # Returns
-    /// - `Result<impl Iterator<Item = S>, E>`:
-    ///   - On success, returns an iterator over the processed items of type `S`.
+    /// - `Result<Outcome<R, T::Record>, E>`:
+    ///   - On success, returns the `Outcome` of the run, see `finish`.
     ///   - On failure, if any item yields an error during processing, returns the first encountered error of type `E`.
     ///
     /// # Errors
-    /// If any item from the input iterator is an `Err`, processing will halt, and the first encountered error will be returned.
+    /// If any item from the input iterator is an `Err`, no more items are submitted, and the first encountered error will be returned
+    /// once the items submitted before it are processed and the shards are finished. Bad items can be skipped up to a budget
+    /// with `fallible::skip_errors`.
+    /// If the runtime can't be started, the `RuntimeError` is returned, which is why `E` must be convertible from it.
     ///
     /// # Notes
     /// - All items must be valid (i.e., `Ok` variants of the `Result`) for the function to succeed.
+    pub fn try_fold<R: Send + 'static, E: From<RuntimeError>>(
+        placement: impl Into<Placement>,
+        assigner: Box<dyn ShardAssigner>,
+        func: F,
+        finish: impl Fn(S) -> R + Clone + Send + 'static,
+        items: impl Iterator<Item = Result<T, E>>,
+    ) -> Result<Outcome<R, T::Record>, E> {
+        let func = move |state: &mut S, item| {
+            func(state, item);
+            Ok::<_, Infallible>(())
+        };
+        ShardedThreadPerCoreRuntime::try_fold_fallible(
+            placement,
+            assigner,
+            func,
+            finish,
+            items,
+            |e| match e {},
+        )
+    }
+
+    /// Submit `items` until they run out, or until `errors` aborts, moving keys between imbalanced
+    /// shards according to `rebalance`, and finish the runtime. Returns the first error of
+    /// `items` once the runtime finished.
+    ///
+    /// # Panics
+    /// If a `rebalance` policy is given, but the runtime wasn't created with `new_migratable`.
+    fn fold<R: Send + 'static, X, E, H: FnMut(E) -> OnError>(
+        self,
+        rebalance: Option<&RebalancePolicy>,
+        finish: impl Fn(S) -> R + Clone + Send + 'static,
+        items: impl Iterator<Item = Result<T, X>>,
+        mut errors: ErrorStream<E, H>,
+    ) -> Result<Outcome<R, T::Record>, X> {
+        let mut pending = None;
+        let mut migrations = Vec::new();
+        let mut submitted = Ok(());
+        for (count, item) in (1..).zip(items) {
+            match item {
+                Ok(item) => self.process_item(item),
+                Err(e) => {
+                    submitted = Err(e);
+                    break;
+                }
+            }
+            if let Some(policy) = rebalance {
+                self.rebalance_step(policy, count, &mut pending, &mut migrations);
+            }
+            if errors.poll().is_break() {
+                break;
+            }
+        }
+        self.finish_migration(pending, &mut migrations);
+        let mut outcome = self.finish_with(finish);
+        outcome.aborted = errors.finish();
+        submitted?;
+        outcome.migrations = migrations;
+        Ok(outcome)
+    }
+
+    /// Move `key` and its state to shard `to`, and route the items of `key` submitted afterwards
+    /// there, see `rebalance`. Returns `None` if the key already is on shard `to` or is moving, or
+    /// if either shard failed: a failed shard has no state to take, and would drop the state put
+    /// on it.
+    ///
+    /// The items of the key stay in order: the state is taken from the old shard after the items
+    /// already queued there, and the items submitted meanwhile are held back until the state is
+    /// queued on shard `to` ahead of them. Only the key is paused, so neither shard waits, but
+    /// `migrate` returns once the old shard has taken out the state.
     ///
     /// # Panics
-    /// - The function will panic if `max_threads` is set to `0`.
-    /// ```
-    pub fn try_fold<E>(
-        max_threads: u8,
+    /// If `to` is not a shard id, or the runtime wasn't created with `new_migratable`.
+    pub fn migrate(&self, key: u64, to: usize) -> Option<Migration> {
+        let pending = self.start_migration(key, to)?;
+        let put = pending.put.recv().ok();
+        self.complete_migration(pending, put)
+    }
+
+    /// Queue taking the state of `key` on its shard, and hold back the items of the key until
+    /// `complete_migration` is called with the state.
+    fn start_migration(&self, key: u64, to: usize) -> Option<PendingMigration<S>> {
+        assert!(to < self.shards.len(), "No shard {to}");
+        let routes = self
+            .routes
+            .as_ref()
+            .expect("Keys are only moved by a runtime created with `new_migratable`");
+        let take = routes.take;
+        let mut routes = routes.moved.write().unwrap_or_else(PoisonError::into_inner);
+        if let Some(Route::Migrating { .. }) = routes.get(&key) {
+            return None;
+        }
+        let from = self.shard_id(&routes, key);
+        let failed = |shard_id: usize| self.shards[shard_id].metrics.is_failed();
+        if from == to || failed(from) || failed(to) {
+            return None;
+        }
+        let (put_tx, put) = channel();
+        let take = move |state: &mut S| {
+            // the migration may have been abandoned
+            let _ = put_tx.send(take(state, key));
+        };
+        self.send(from, Message::Call(Box::new(take)));
+        let held = Mutex::default();
+        routes.insert(key, Route::Migrating { to, held });
+        Some(PendingMigration {
+            migration: Migration { key, from, to },
+            put,
+        })
+    }
+
+    /// Queue the state taken from the old shard on the new shard, followed by the items held back
+    /// meanwhile. If the old shard failed before taking the state, which `finish` reports, the key
+    /// stays on it and `None` is returned.
+    fn complete_migration(
+        &self,
+        pending: PendingMigration<S>,
+        put: Option<Option<Call<S>>>,
+    ) -> Option<Migration> {
+        let Migration { key, from, to } = pending.migration;
+        let mut routes = self
+            .routes
+            .as_ref()
+            .expect("A migration was started, so the runtime has routes")
+            .moved
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(Route::Migrating { held, .. }) = routes.remove(&key) else {
+            unreachable!("Only the pending migration of a key completes it");
+        };
+        let moved = put.is_some();
+        let shard_id = if moved { to } else { from };
+        if let Some(Some(put)) = put {
+            self.send(to, Message::Call(put));
+        }
+        for message in held.into_inner().unwrap_or_else(PoisonError::into_inner) {
+            self.send(shard_id, message);
+        }
+        if self.assigner.assign(key, self.shards.len()) != shard_id {
+            routes.insert(key, Route::Moved(shard_id));
+        }
+        moved.then_some(pending.migration)
+    }
+
+    /// Complete `pending` if the old shard took out the state, or return it otherwise.
+    fn poll_migration(
+        &self,
+        pending: PendingMigration<S>,
+    ) -> Result<Option<Migration>, PendingMigration<S>> {
+        match pending.put.try_recv() {
+            Ok(put) => Ok(self.complete_migration(pending, Some(put))),
+            Err(TryRecvError::Disconnected) => Ok(self.complete_migration(pending, None)),
+            Err(TryRecvError::Empty) => Err(pending),
+        }
+    }
+
+    /// Move a key from the busiest shard to the least busy one, if the depth of their queues is
+    /// imbalanced according to `policy`. The key is picked from the keys recently processed by the
+    /// busiest shard, see `metrics::ShardMetrics::sampled_keys`.
+    ///
+    /// # Panics
+    /// If the runtime wasn't created with `new_migratable`.
+    pub fn rebalance(&self, policy: &RebalancePolicy) -> Option<Migration> {
+        let migration = self.plan(policy)?;
+        self.migrate(migration.key, migration.to)
+    }
+
+    fn plan(&self, policy: &RebalancePolicy) -> Option<Migration> {
+        let routes = self
+            .routes
+            .as_ref()
+            .expect("Keys are only moved by a runtime created with `new_migratable`");
+        policy.plan(
+            &self.stats(),
+            |shard_id| self.shards[shard_id].metrics.sampled_keys(),
+            |key| {
+                let routes = routes.moved.read().unwrap_or_else(PoisonError::into_inner);
+                self.shard_id(&routes, key)
+            },
+        )
+    }
+
+    /// Check for imbalanced shards every `REBALANCE_EVERY` items while submitting, and start
+    /// moving a key, or complete the move started before, without waiting for the old shard.
+    fn rebalance_step(
+        &self,
+        policy: &RebalancePolicy,
+        count: u64,
+        pending: &mut Option<PendingMigration<S>>,
+        migrations: &mut Vec<Migration>,
+    ) {
+        if !count.is_multiple_of(REBALANCE_EVERY) {
+            return;
+        }
+        match pending.take().map(|pending| self.poll_migration(pending)) {
+            Some(Ok(migration)) => migrations.extend(migration),
+            Some(Err(still_pending)) => *pending = Some(still_pending),
+            None => {
+                *pending = self
+                    .plan(policy)
+                    .and_then(|migration| self.start_migration(migration.key, migration.to));
+            }
+        }
+    }
+
+    /// Wait for the migration still pending after the last item was submitted.
+    fn finish_migration(
+        &self,
+        pending: Option<PendingMigration<S>>,
+        migrations: &mut Vec<Migration>,
+    ) {
+        if let Some(pending) = pending {
+            let put = pending.put.recv().ok();
+            migrations.extend(self.complete_migration(pending, put));
+        }
+    }
+}
+
+/// The runtime of a fold function `G` returning `Result<(), E>`, which runs a wrapper of `G`
+/// sending the errors back, see `fallible`.
+impl<T, G, S, E> ShardedThreadPerCoreRuntime<T, G, S>
+where
+    T: Send + Shardable + Quarantine + 'static,
+    G: Fn(&mut S, T) -> Result<(), E> + Clone + Send + 'static,
+    S: Default + Send + 'static,
+    E: Send + 'static,
+{
+    /// Like `try_fold`, but `func` may fail. Its errors are handed to `on_error` on the calling
+    /// thread, between two submitted items. Once `on_error` returns `OnError::Abort`, no more
+    /// items are submitted, and the outcome is marked as `aborted`. The items submitted before
+    /// are still processed, and their errors handed to `on_error` as well.
+    pub fn try_fold_fallible<R: Send + 'static, X: From<RuntimeError>>(
+        placement: impl Into<Placement>,
+        assigner: Box<dyn ShardAssigner>,
+        func: G,
+        finish: impl Fn(S) -> R + Clone + Send + 'static,
+        items: impl Iterator<Item = Result<T, X>>,
+        on_error: impl FnMut(E) -> OnError,
+    ) -> Result<Outcome<R, T::Record>, X> {
+        let (errors_tx, errors) = channel();
+        let func = fallible::reporting(func, errors_tx);
+        let rt = ShardedThreadPerCoreRuntime::new(placement, assigner, func)?;
+        rt.fold(None, finish, items, ErrorStream::new(errors, on_error))
+    }
+}
+
+impl<T, F, S> ShardedThreadPerCoreRuntime<T, F, S>
+where
+    T: Send + Shardable + Quarantine + 'static,
+    F: Fn(&mut S, T) + Clone + Send + 'static,
+    S: Default + Migrate + Send + 'static,
+{
+    /// Like `new`, but keys can be moved between the shards with `migrate` and `rebalance`. The
+    /// items are routed through a table of the moved keys, which takes a read lock per item.
+    pub fn new_migratable(
+        placement: impl Into<Placement>,
+        assigner: Box<dyn ShardAssigner>,
         func: F,
-        items: impl Iterator<Item = Result<T, E>>,
-    ) -> Result<impl Iterator<Item = S>, E> {
-        let rt = Self::new(max_threads, func);
-        for item in items {
-            rt.process_item(item?)
+    ) -> Result<Self, RuntimeError> {
+        Self::start(placement.into(), assigner, func, Some(take::<S>))
+    }
+}
+
+impl<T, G, S, E> ShardedThreadPerCoreRuntime<T, G, S>
+where
+    T: Send + Shardable + Quarantine + 'static,
+    G: Fn(&mut S, T) -> Result<(), E> + Clone + Send + 'static,
+    S: Default + Migrate + Send + 'static,
+    E: Send + 'static,
+{
+    /// Like `try_fold_fallible`, but if a `rebalance` policy is given, checks for imbalanced
+    /// shards while submitting the items, and moves keys between them according to the policy,
+    /// see `rebalance`. The migrations are part of the outcome.
+    pub fn try_fold_rebalanced<R: Send + 'static, X: From<RuntimeError>>(
+        placement: impl Into<Placement>,
+        assigner: Box<dyn ShardAssigner>,
+        rebalance: Option<&RebalancePolicy>,
+        func: G,
+        finish: impl Fn(S) -> R + Clone + Send + 'static,
+        items: impl Iterator<Item = Result<T, X>>,
+        on_error: impl FnMut(E) -> OnError,
+    ) -> Result<Outcome<R, T::Record>, X> {
+        let (errors_tx, errors) = channel();
+        let func = fallible::reporting(func, errors_tx);
+        let rt = match rebalance {
+            Some(_) => ShardedThreadPerCoreRuntime::new_migratable(placement, assigner, func)?,
+            None => ShardedThreadPerCoreRuntime::new(placement, assigner, func)?,
+        };
+        rt.fold(rebalance, finish, items, ErrorStream::new(errors, on_error))
+    }
+}
+
+/// Merges the per-shard results of a run into one iterator ordered by key.
+///
+/// Every shard owns a disjoint set of keys, so once each shard's result is sorted, a k-way merge
+/// yields a globally sorted sequence without collecting and sorting everything again. The output
+/// order is independent of the number of shards, which makes output files comparable across
+/// machines with different core counts.
+///
+/// Each input iterator must yield its items in ascending key order.
+pub struct SortedMerge<K, V, I> {
+    sources: Vec<I>,
+    // the next item of every source that is not yet exhausted, keyed by (key, source index) so
+    // the smallest key is on top of the heap
+    heads: BinaryHeap<Reverse<(K, usize)>>,
+    values: Vec<Option<V>>,
+}
+
+impl<K, V, I> SortedMerge<K, V, I>
+where
+    K: Ord,
+    I: Iterator<Item = (K, V)>,
+{
+    pub fn new(sources: impl IntoIterator<Item = I>) -> Self {
+        let mut sources = sources.into_iter().collect::<Vec<_>>();
+        let mut heads = BinaryHeap::with_capacity(sources.len());
+        let mut values = Vec::with_capacity(sources.len());
+        for (index, source) in sources.iter_mut().enumerate() {
+            values.push(source.next().map(|(key, value)| {
+                heads.push(Reverse((key, index)));
+                value
+            }));
+        }
+        Self {
+            sources,
+            heads,
+            values,
+        }
+    }
+}
+
+impl<K, V, I> Iterator for SortedMerge<K, V, I>
+where
+    K: Ord,
+    I: Iterator<Item = (K, V)>,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((key, index)) = self.heads.pop()?;
+        let value = self.values[index].take().expect("Merge head without value"); // this would be a bug
+        // refill the head of the source we just took an item from
+        if let Some((next_key, next_value)) = self.sources[index].next() {
+            self.heads.push(Reverse((next_key, index)));
+            self.values[index] = Some(next_value);
         }
-        Ok(rt.finish().into_iter())
+        Some((key, value))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::convert::Infallible;
+    use assign::Modulo;
+    use rebalance::Migrate;
+    use std::panic::panic_any;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-    #[test]
-    fn test_runtime() {
-        struct Item {
-            id: u32,
-            value: u32,
+    /// An item with a key and a value, quarantined as its value
+    struct Item(u64, u32);
+    impl Shardable for Item {
+        fn shard_key(&self) -> u64 {
+            self.0
         }
-        impl Shardable for Item {
-            fn shard_id(&self, num_shards: u8) -> usize {
-                self.id as usize % num_shards as usize
-            }
+    }
+    impl Quarantine for Item {
+        type Record = u32;
+        fn record(&self) -> u32 {
+            self.1
         }
+    }
 
+    /// An item whose key is its value
+    fn item(value: u32) -> Item {
+        Item(value.into(), value)
+    }
+
+    #[test]
+    fn test_runtime() {
         let result = ShardedThreadPerCoreRuntime::<Item, _, [u32; 2]>::try_fold(
             4,
-            |s, x| s[x.id as usize] += x.value,
+            Box::new(Modulo),
+            |s, x| s[x.0 as usize] += x.1,
+            identity,
             vec![
-                Ok::<_, Infallible>(Item { id: 0, value: 1 }),
-                Ok(Item { id: 1, value: 2 }),
-                Ok(Item { id: 0, value: 3 }),
-                Ok(Item { id: 1, value: 4 }),
+                Ok::<_, RuntimeError>(Item(0, 1)),
+                Ok(Item(1, 2)),
+                Ok(Item(0, 3)),
+                Ok(Item(1, 4)),
             ]
             .into_iter(),
         )
+        .unwrap()
+        .states()
+        .unwrap()
         .into_iter()
-        .flatten()
         .reduce(|a, b| [a[0] + b[0], a[1] + b[1]])
         .unwrap();
         assert_eq!(result, [4, 6]);
     }
+
+    #[test]
+    fn test_no_shards() {
+        let result = ShardedThreadPerCoreRuntime::<Item, _, ()>::try_fold(
+            0,
+            Box::new(Modulo),
+            |_, _| {},
+            identity,
+            std::iter::once(Ok(Item(0, 0))),
+        );
+        assert_eq!(result.err(), Some(RuntimeError::NoShards));
+    }
+
+    #[test]
+    fn test_error_finishes_shards() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        /// Counts the shard states dropped, which happens when their threads exit
+        #[derive(Default)]
+        struct State;
+        impl Drop for State {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let result = ShardedThreadPerCoreRuntime::<Item, _, State>::try_fold(
+            2,
+            Box::new(Modulo),
+            |_, _| {},
+            identity,
+            [Ok(Item(0, 0)), Err(RuntimeError::NoCores), Ok(Item(0, 0))].into_iter(),
+        );
+        assert_eq!(result.err(), Some(RuntimeError::NoCores));
+        // the shards were joined before the error was returned
+        let shards = affinity::CoreList::available().unwrap().ids().len().min(2);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), shards);
+    }
+
+    #[test]
+    fn test_call_and_snapshot() {
+        let rt =
+            ShardedThreadPerCoreRuntime::<Item, _, u32>::new(2, Box::new(Modulo), |s, x: Item| {
+                *s += x.1
+            })
+            .unwrap();
+        let shards = rt.shards.len() as u32;
+        for i in 0..10 {
+            rt.process_item(item(i));
+        }
+        let sums = rt
+            .snapshot(|s| *s)
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(sums.iter().sum::<u32>(), 45);
+        // the state can be changed by a call, which is only seen by items after it
+        assert_eq!(rt.call(&item(0), std::mem::take), Ok(sums[0]));
+        rt.process_item(item(shards));
+        assert_eq!(rt.call(&item(shards), |s| *s), Ok(shards));
+        let states = rt.finish().states().unwrap();
+        assert_eq!(states.iter().sum::<u32>(), 45 - sums[0] + shards);
+    }
+
+    #[test]
+    fn test_finish_with() {
+        let rt = ShardedThreadPerCoreRuntime::<Item, _, Vec<u32>>::new(
+            2,
+            Box::new(Modulo),
+            |s, x: Item| s.push(x.1),
+        )
+        .unwrap();
+        for i in (0..10).rev() {
+            rt.process_item(item(i));
+        }
+        let main = std::thread::current().id();
+        let outcome = rt.finish_with(move |mut values| {
+            assert_ne!(std::thread::current().id(), main);
+            values.sort_unstable();
+            values
+        });
+        let states = outcome.states().unwrap();
+        assert!(states.iter().all(|values| values.is_sorted()));
+        assert_eq!(states.concat().len(), 10);
+
+        let rt = ShardedThreadPerCoreRuntime::<Item, _, u32>::new(1, Box::new(Modulo), |_, _| {})
+            .unwrap();
+        let outcome = rt.finish_with(|_| -> u32 { panic!("cannot finish") });
+        assert_eq!(
+            outcome.failures().collect::<Vec<_>>(),
+            [&ShardFailure {
+                shard: 0,
+                message: "cannot finish".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let outcome = ShardedThreadPerCoreRuntime::<Item, _, u32>::try_fold(
+            2,
+            Box::new(Modulo),
+            |s, x: Item| *s += x.1,
+            identity,
+            (0..100)
+                .map(Ok::<_, RuntimeError>)
+                .map(|i| i.map(|i| Item(0, i))),
+        )
+        .unwrap();
+        let stats = outcome.stats.clone();
+        assert_eq!(outcome.states().unwrap()[0], 4950);
+        assert_eq!(stats[0].items, 100);
+        assert_eq!(stats[0].latency.count(), 100);
+        assert_eq!(stats[0].queue_depth, 0);
+        assert!(stats[0].queue_high_water_mark >= 1);
+        assert!(stats[1..].iter().all(|stats| stats.items == 0));
+    }
+
+    #[test]
+    fn test_migrate() {
+        /// The values of every key, in the order they were processed
+        #[derive(Default)]
+        struct Values(FnvHashMap<u64, Vec<u32>>);
+        impl Migrate for Values {
+            type Part = Vec<u32>;
+            fn take(&mut self, key: u64) -> Option<Vec<u32>> {
+                self.0.remove(&key)
+            }
+            fn put(&mut self, key: u64, values: Vec<u32>) {
+                self.0.insert(key, values);
+            }
+        }
+
+        let rt = ShardedThreadPerCoreRuntime::new_migratable(
+            2,
+            Box::new(Modulo),
+            |s: &mut Values, x: Item| s.0.entry(x.0).or_default().push(x.1),
+        )
+        .unwrap();
+        let to = 1 % rt.shards.len();
+        for i in 0..300 {
+            rt.process_item(Item(i as u64 % 3, i));
+            if i == 100 {
+                let migration = rt.migrate(0, to);
+                assert_eq!(migration.is_some(), to != 0);
+                assert_eq!(rt.migrate(0, to), None);
+            }
+            if i == 200 {
+                rt.migrate(0, 0);
+                assert!(rt.routes.as_ref().unwrap().moved.read().unwrap().is_empty());
+            }
+        }
+        let states = rt.finish().states().unwrap();
+        let values = states.iter().flat_map(|s| s.0.get(&0)).collect::<Vec<_>>();
+        assert_eq!(values, [&(0..300).step_by(3).collect::<Vec<_>>()]);
+
+        // the new shard keeps processing its own keys while the old one hasn't taken the state
+        let rt = ShardedThreadPerCoreRuntime::new_migratable(
+            2,
+            Box::new(Modulo),
+            |s: &mut Values, x: Item| s.0.entry(x.0).or_default().push(x.1),
+        )
+        .unwrap();
+        if rt.shards.len() > 1 {
+            let (release, blocked) = channel::<()>();
+            rt.process_item(Item(0, 0));
+            rt.send(0, Message::Call(Box::new(move |_| blocked.recv().unwrap())));
+            let pending = rt.start_migration(0, 1).unwrap();
+            rt.process_item(Item(0, 1));
+            rt.process_item(Item(1, 0));
+            let values = rt.call(&Item(1, 0), |s: &mut Values| s.0.clone()).unwrap();
+            assert_eq!(values.into_iter().collect::<Vec<_>>(), [(1, vec![0])]);
+            release.send(()).unwrap();
+            let put = pending.put.recv().ok();
+            assert!(rt.complete_migration(pending, put).is_some());
+            let states = rt.finish().states().unwrap();
+            assert_eq!(states[1].0[&0], [0, 1]);
+        }
+
+        // nothing moves to or from a failed shard
+        let rt = ShardedThreadPerCoreRuntime::new_migratable(
+            2,
+            Box::new(Modulo),
+            |s: &mut Values, x: Item| s.0.entry(x.0).or_default().push(x.1),
+        )
+        .unwrap();
+        let failed = rt.shards.len() - 1;
+        rt.process_item(Item(0, 0));
+        rt.send(failed, Message::Call(Box::new(|_| panic!("failed"))));
+        let (reply, dropped) = channel();
+        rt.send(
+            failed,
+            Message::Call(Box::new(move |_| reply.send(()).unwrap())),
+        );
+        assert!(dropped.recv().is_err());
+        assert!(rt.stats()[failed].failed);
+        assert_eq!(rt.migrate(0, failed), None);
+        assert_eq!(rt.migrate(failed as u64, 0), None);
+        assert_eq!(rt.finish().failures().count(), 1);
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let outcome = ShardedThreadPerCoreRuntime::<Item, _, u32>::try_fold(
+            2,
+            Box::new(Modulo),
+            |s, x: Item| {
+                match x.1 {
+                    3 => panic!("cannot process {}", x.1),
+                    5 => panic_any(5),
+                    _ => {}
+                }
+                *s += x.1
+            },
+            identity,
+            (0..10)
+                .map(Ok::<_, RuntimeError>)
+                .map(|i| i.map(|i| Item(0, i))),
+        )
+        .unwrap();
+        assert_eq!(
+            outcome.quarantined,
+            [
+                Quarantined {
+                    shard: 0,
+                    item: 3,
+                    message: "cannot process 3".to_string()
+                },
+                Quarantined {
+                    shard: 0,
+                    item: 5,
+                    message: "unknown panic".to_string()
+                }
+            ]
+        );
+        assert_eq!(outcome.stats[0].items, 10);
+        assert_eq!(outcome.stats[0].panics, 2);
+        // the shard kept processing the items after the panics
+        assert_eq!(outcome.states().unwrap()[0], 45 - 3 - 5);
+    }
+
+    #[test]
+    fn test_shard_failure() {
+        let rt =
+            ShardedThreadPerCoreRuntime::<Item, _, u32>::new(2, Box::new(Modulo), |s, x: Item| {
+                *s += x.1
+            })
+            .unwrap();
+        let shards = rt.shards.len() as u32;
+        rt.process_item(item(0));
+        // a panicking call is returned to the caller, and the shard keeps its state
+        let call = rt.call(&item(0), |s| -> () {
+            *s += 1;
+            panic!("broken call")
+        });
+        let failure = ShardFailure {
+            shard: 0,
+            message: "broken call".to_string(),
+        };
+        assert_eq!(call, Err(failure));
+        assert_eq!(rt.call(&item(0), |s| *s), Ok(1));
+        if shards > 1 {
+            let snapshot = rt.snapshot(|s| if *s == 1 { panic!("broken call") } else { *s });
+            assert_eq!(snapshot[0].as_ref().unwrap_err().message, "broken call");
+            assert_eq!(snapshot[1], Ok(0));
+        }
+
+        // a panicking migration stops the shard thread
+        rt.send(0, Message::Call(Box::new(|_| panic!("broken shard"))));
+        let failure = ShardFailure {
+            shard: 0,
+            message: "the shard failed".to_string(),
+        };
+        assert_eq!(rt.call(&item(0), |s| *s), Err(failure.clone()));
+        if shards > 1 {
+            assert_eq!(rt.snapshot(|s| *s)[..2], [Err(failure), Ok(0)]);
+        }
+        rt.process_item(item(shards));
+
+        let outcome = rt.finish();
+        assert_eq!(
+            outcome.failures().collect::<Vec<_>>(),
+            [&ShardFailure {
+                shard: 0,
+                message: "broken shard".to_string()
+            }]
+        );
+        assert_eq!(
+            outcome.quarantined,
+            [Quarantined {
+                shard: 0,
+                item: shards,
+                message: "the shard failed".to_string()
+            }]
+        );
+        assert_eq!(outcome.states().err().map(|failure| failure.shard), Some(0));
+    }
+
+    #[test]
+    fn test_fallible() {
+        let odd = |s: &mut u32, x: Item| {
+            if x.1 % 2 == 1 {
+                return Err(x.1);
+            }
+            *s += x.1;
+            Ok(())
+        };
+
+        let mut errors = Vec::new();
+        let outcome = ShardedThreadPerCoreRuntime::try_fold_fallible(
+            2,
+            Box::new(Modulo),
+            odd,
+            identity,
+            (0..100).map(Ok::<_, RuntimeError>).map(|i| i.map(item)),
+            |e| {
+                errors.push(e);
+                OnError::Continue
+            },
+        )
+        .unwrap();
+        assert!(!outcome.aborted);
+        assert_eq!(outcome.states().unwrap().iter().sum::<u32>(), 2450);
+        errors.sort_unstable();
+        assert_eq!(errors, (1..100).step_by(2).collect::<Vec<_>>());
+
+        let mut errors = 0;
+        let outcome = ShardedThreadPerCoreRuntime::try_fold_fallible(
+            2,
+            Box::new(Modulo),
+            odd,
+            identity,
+            // endless, so the run only ends if it is aborted
+            (0..).map(Ok::<_, RuntimeError>).map(|i| i.map(item)),
+            |_| {
+                errors += 1;
+                OnError::Abort
+            },
+        )
+        .unwrap();
+        assert!(outcome.aborted);
+        assert!(errors >= 1);
+    }
+
+    #[test]
+    fn test_sorted_merge() {
+        let shards = vec![
+            vec![(1, 'a'), (4, 'd'), (7, 'g')],
+            vec![],
+            vec![(2, 'b'), (3, 'c'), (9, 'i')],
+            vec![(5, 'e')],
+        ];
+        let merged = SortedMerge::new(shards.into_iter().map(Vec::into_iter)).collect::<Vec<_>>();
+        assert_eq!(
+            merged,
+            [
+                (1, 'a'),
+                (2, 'b'),
+                (3, 'c'),
+                (4, 'd'),
+                (5, 'e'),
+                (7, 'g'),
+                (9, 'i')
+            ]
+        );
+    }
 }
@@ -0,0 +1,200 @@
+//! Placing the shards of a `ShardedThreadPerCoreRuntime` on the cores of the machine.
+//!
+//! By default, every shard is pinned to its own core, taking the cores in the order the operating
+//! system lists them. A `Placement` restricts the shards to a list of cores, for example to keep
+//! them away from other services, and reserves a core for the thread submitting the items. On a
+//! machine with several NUMA nodes, the shards are spread over the nodes, see `numa`.
+
+use super::RuntimeError;
+use super::numa::Topology;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// A list of core ids, written like `2-15` or `0,2,4-7`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoreList(Vec<usize>);
+
+impl CoreList {
+    /// All cores the runtime can pin shards to.
+    pub fn available() -> Result<Self, RuntimeError> {
+        let core_ids = core_affinity::get_core_ids()
+            .filter(|core_ids| !core_ids.is_empty())
+            .ok_or(RuntimeError::NoCores)?;
+        Ok(Self(
+            core_ids.into_iter().map(|core_id| core_id.id).collect(),
+        ))
+    }
+
+    pub fn ids(&self) -> &[usize] {
+        &self.0
+    }
+}
+
+impl FromStr for CoreList {
+    type Err = String;
+
+    fn from_str(list: &str) -> Result<Self, String> {
+        let mut ids = Vec::new();
+        for part in list.split(',') {
+            let parse = |id: &str| {
+                id.trim()
+                    .parse::<usize>()
+                    .map_err(|e| format!("invalid core id {id:?}: {e}"))
+            };
+            let range = match part.split_once('-') {
+                Some((first, last)) => parse(first)?..=parse(last)?,
+                None => parse(part)?..=parse(part)?,
+            };
+            if range.is_empty() {
+                return Err(format!("empty core range {part:?}"));
+            }
+            for id in range {
+                if ids.contains(&id) {
+                    return Err(format!("core {id} is listed twice"));
+                }
+                ids.push(id);
+            }
+        }
+        Ok(Self(ids))
+    }
+}
+
+/// Writes the list with consecutive ids collapsed into ranges, like it is parsed
+impl Display for CoreList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut ids = self.0.iter().copied().peekable();
+        let mut separator = "";
+        while let Some(first) = ids.next() {
+            let mut last = first;
+            while ids.next_if_eq(&(last + 1)).is_some() {
+                last += 1;
+            }
+            match last - first {
+                0 => write!(f, "{separator}{first}")?,
+                1 => write!(f, "{separator}{first},{last}")?,
+                _ => write!(f, "{separator}{first}-{last}")?,
+            }
+            separator = ",";
+        }
+        Ok(())
+    }
+}
+
+/// Where the threads of a runtime run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placement {
+    /// The most shards to start, one per core
+    pub max_threads: usize,
+    /// The cores to run the shards on, in shard order [default: all available cores but the
+    /// reader core]
+    pub cores: Option<CoreList>,
+    /// Whether to pin every shard thread to its core. Unpinned shards are still started on the
+    /// same number of cores.
+    pub pin: bool,
+    /// The core to pin the thread creating the runtime to, which submits the items, see
+    /// `ShardedThreadPerCoreRuntime::new`
+    pub reader_core: Option<usize>,
+    /// The NUMA nodes of the cores, discovered once for the runtime and its report, see `numa`
+    pub topology: Topology,
+}
+
+/// Places up to `max_threads` shards on all available cores, pinned
+impl From<usize> for Placement {
+    fn from(max_threads: usize) -> Self {
+        Self {
+            max_threads,
+            cores: None,
+            pin: true,
+            reader_core: None,
+            topology: Topology::discover(),
+        }
+    }
+}
+
+impl Placement {
+    /// The core of every shard, in shard order. Without an explicit list of cores, the available
+    /// cores are interleaved over the NUMA nodes, see `numa::Topology::interleave`, and the reader
+    /// core is left to the reader, unless it is the only core available.
+    pub fn shard_cores(&self) -> Result<CoreList, RuntimeError> {
+        if self.max_threads == 0 {
+            return Err(RuntimeError::NoShards);
+        }
+        let available = CoreList::available()?;
+        let listed = self.cores.iter().flat_map(CoreList::ids);
+        if let Some(&id) = listed
+            .chain(&self.reader_core)
+            .find(|id| !available.ids().contains(id))
+        {
+            return Err(RuntimeError::UnavailableCore(id));
+        }
+        let mut cores = match &self.cores {
+            Some(cores) => cores.clone(),
+            None => {
+                let mut cores = self.topology.interleave(available.ids());
+                if cores.len() > 1 {
+                    cores.retain(|&id| Some(id) != self.reader_core);
+                }
+                CoreList(cores)
+            }
+        };
+        if cores.0.is_empty() {
+            return Err(RuntimeError::NoCores);
+        }
+        cores.0.truncate(self.max_threads);
+        Ok(cores)
+    }
+}
+
+/// Pin the current thread to `core`, if it is available.
+pub(super) fn pin_current_thread(core: usize) {
+    core_affinity::set_for_current(core_affinity::CoreId { id: core });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_core_list() {
+        let list = "2-5,0,8".parse::<CoreList>().unwrap();
+        assert_eq!(list.ids(), [2, 3, 4, 5, 0, 8]);
+        assert_eq!(list.to_string(), "2-5,0,8");
+        assert_eq!("1,2,4".parse::<CoreList>().unwrap().to_string(), "1,2,4");
+        assert!("3-1".parse::<CoreList>().is_err());
+        assert!("1,1".parse::<CoreList>().is_err());
+        assert!("x".parse::<CoreList>().is_err());
+        assert!("".parse::<CoreList>().is_err());
+    }
+
+    #[test]
+    fn test_shard_cores() {
+        let available = CoreList::available().unwrap();
+        let first = available.ids()[0];
+        assert_eq!(Placement::from(1).shard_cores().unwrap().ids(), [first]);
+        assert_eq!(
+            Placement::from(0).shard_cores(),
+            Err(RuntimeError::NoShards)
+        );
+        let placement = Placement {
+            cores: Some(CoreList(vec![first])),
+            reader_core: Some(first),
+            ..Placement::from(4)
+        };
+        assert_eq!(placement.shard_cores().unwrap().ids(), [first]);
+        let placement = Placement {
+            cores: Some(CoreList(vec![usize::MAX])),
+            ..Placement::from(4)
+        };
+        assert_eq!(
+            placement.shard_cores(),
+            Err(RuntimeError::UnavailableCore(usize::MAX))
+        );
+        // the reader core is left out, unless there is no other core
+        let placement = Placement {
+            reader_core: Some(first),
+            ..Placement::from(available.0.len())
+        };
+        let cores = placement.shard_cores().unwrap();
+        assert_eq!(cores.0.len(), available.0.len().max(2) - 1);
+    }
+}
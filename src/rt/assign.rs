@@ -0,0 +1,213 @@
+//! Strategies assigning items to the shards of a `ShardedThreadPerCoreRuntime`.
+//!
+//! Items are routed by the key returned by `Shardable::shard_key`. An assignment only depends on
+//! the key and the number of shards, so all items with the same key are processed by the same
+//! shard, in the order they were submitted.
+//!
+//! The default `Modulo` assignment spreads keys evenly, but not their load: when a few keys carry
+//! most of the items, the shards they land on saturate while the others sit idle. `Overrides` pins
+//! such keys to dedicated shards, which can be loaded from a config file, see `AssignmentConfig`.
+
+use fnv::FnvHashMap;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Assigns the key of an item to a shard.
+pub trait ShardAssigner: Send + Sync {
+    /// The shard of `key`, below `num_shards`, which is at least 1. Must always return the same
+    /// shard for the same arguments.
+    fn assign(&self, key: u64, num_shards: usize) -> usize;
+}
+
+/// Assigns `key % num_shards`, which spreads sequential keys evenly. This is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Modulo;
+
+impl ShardAssigner for Modulo {
+    fn assign(&self, key: u64, num_shards: usize) -> usize {
+        (key % num_shards as u64) as usize
+    }
+}
+
+/// Mixes the bits of the key before taking the modulo, so keys sharing a stride, like client ids
+/// that are all multiples of the number of shards, are still spread evenly.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HashMix;
+
+impl ShardAssigner for HashMix {
+    fn assign(&self, key: u64, num_shards: usize) -> usize {
+        (mix(key) % num_shards as u64) as usize
+    }
+}
+
+/// The finalizer of splitmix64, a cheap bijection that changes about half of the output bits for
+/// every changed input bit.
+fn mix(key: u64) -> u64 {
+    let mut x = key;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Pins keys to shards with an explicit table, and assigns all other keys with a `fallback`
+/// strategy.
+///
+/// Pinned shards are dedicated to their keys: the fallback only assigns to the remaining shards,
+/// so a heavy key doesn't share its shard with others. Several keys may be pinned to the same
+/// shard. If every shard is pinned, the fallback assigns to all of them. A key pinned to a shard
+/// that doesn't exist is assigned to the pinned shard modulo the number of shards.
+pub struct Overrides {
+    pinned: FnvHashMap<u64, usize>,
+    // the pinned shards, in ascending order
+    dedicated: Vec<usize>,
+    fallback: Box<dyn ShardAssigner>,
+}
+
+impl Overrides {
+    pub fn new(
+        pinned: impl IntoIterator<Item = (u64, usize)>,
+        fallback: Box<dyn ShardAssigner>,
+    ) -> Self {
+        let pinned = pinned.into_iter().collect::<FnvHashMap<_, _>>();
+        let mut dedicated = pinned.values().copied().collect::<Vec<_>>();
+        dedicated.sort_unstable();
+        dedicated.dedup();
+        Self {
+            pinned,
+            dedicated,
+            fallback,
+        }
+    }
+}
+
+impl ShardAssigner for Overrides {
+    fn assign(&self, key: u64, num_shards: usize) -> usize {
+        if let Some(shard) = self.pinned.get(&key) {
+            return shard % num_shards;
+        }
+        let dedicated = &self.dedicated[..self.dedicated.partition_point(|&s| s < num_shards)];
+        if dedicated.len() >= num_shards {
+            return self.fallback.assign(key, num_shards);
+        }
+        // assign among the free shards, then skip over the dedicated ones below the result
+        let mut shard = self.fallback.assign(key, num_shards - dedicated.len());
+        for &dedicated in dedicated {
+            if dedicated <= shard {
+                shard += 1;
+            }
+        }
+        shard
+    }
+}
+
+/// The strategy assigning keys that are not pinned
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    #[default]
+    Modulo,
+    Hash,
+}
+
+/// A shard assignment as written in a TOML config file, e.g.
+///
+/// ```toml
+/// strategy = "hash"
+///
+/// # key = shard
+/// [pin]
+/// 17 = 0
+/// 42 = 1
+/// ```
+///
+/// Both fields are optional; an empty file is the default `Modulo` assignment.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AssignmentConfig {
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// The keys pinned to a dedicated shard
+    #[serde(default)]
+    pub pin: BTreeMap<u64, usize>,
+}
+
+impl AssignmentConfig {
+    pub fn from_toml(config: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(config)
+    }
+
+    pub fn assigner(&self) -> Box<dyn ShardAssigner> {
+        let strategy: Box<dyn ShardAssigner> = match self.strategy {
+            Strategy::Modulo => Box::new(Modulo),
+            Strategy::Hash => Box::new(HashMix),
+        };
+        if self.pin.is_empty() {
+            strategy
+        } else {
+            let pinned = self.pin.iter().map(|(&key, &shard)| (key, shard));
+            Box::new(Overrides::new(pinned, strategy))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_mix() {
+        // keys with the stride of the number of shards all land on one shard with modulo
+        let mut per_shard = [0; 4];
+        for key in (0..4000).step_by(4) {
+            assert_eq!(Modulo.assign(key, 4), 0);
+            per_shard[HashMix.assign(key, 4)] += 1;
+        }
+        assert!(per_shard.iter().all(|&count| count > 200), "{per_shard:?}");
+    }
+
+    #[test]
+    fn test_overrides() {
+        let overrides = Overrides::new([(7, 1), (8, 1), (9, 3)], Box::new(Modulo));
+        assert_eq!(overrides.assign(7, 4), 1);
+        assert_eq!(overrides.assign(8, 4), 1);
+        assert_eq!(overrides.assign(9, 4), 3);
+        // the other keys are spread over shards 0 and 2
+        let others = (0..7)
+            .map(|key| overrides.assign(key, 4))
+            .collect::<Vec<_>>();
+        assert_eq!(others, [0, 2, 0, 2, 0, 2, 0]);
+        // with fewer shards, shard 3 doesn't exist and only shard 1 is dedicated
+        assert_eq!(overrides.assign(9, 3), 0);
+        let others = (0..4)
+            .map(|key| overrides.assign(key, 3))
+            .collect::<Vec<_>>();
+        assert_eq!(others, [0, 2, 0, 2]);
+        assert_eq!(overrides.assign(5, 2), 0);
+        // if every shard is pinned, the fallback assigns to all of them
+        let overrides = Overrides::new([(7, 0), (8, 1)], Box::new(Modulo));
+        assert_eq!(overrides.assign(5, 2), 1);
+    }
+
+    #[test]
+    fn test_config() {
+        let config = AssignmentConfig::from_toml("strategy = \"hash\"\n[pin]\n17 = 0\n42 = 1\n");
+        assert_eq!(
+            config.unwrap(),
+            AssignmentConfig {
+                strategy: Strategy::Hash,
+                pin: BTreeMap::from([(17, 0), (42, 1)]),
+            }
+        );
+        assert_eq!(AssignmentConfig::from_toml("").unwrap(), Default::default());
+        assert!(AssignmentConfig::from_toml("strategy = \"random\"").is_err());
+        assert!(AssignmentConfig::from_toml("[pin]\nx = 1").is_err());
+
+        let assigner = AssignmentConfig::from_toml("[pin]\n1 = 0")
+            .unwrap()
+            .assigner();
+        let shards = (0..4)
+            .map(|key| assigner.assign(key, 3))
+            .collect::<Vec<_>>();
+        assert_eq!(shards, [1, 0, 1, 2]);
+    }
+}
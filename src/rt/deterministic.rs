@@ -0,0 +1,284 @@
+//! A drop-in replacement for `ShardedThreadPerCoreRuntime::try_fold` running every shard on the
+//! calling thread, for debugging and testing.
+//!
+//! The items are routed to the same shards as on the threaded runtime, and folded one at a time in
+//! the order they are submitted, so the items of every shard are processed in the same order on
+//! every run, regardless of the number of cores or the scheduling of threads. The outcome is the
+//! same as of a threaded run with the same number of shards, except for the timings in its
+//! metrics, so a failure can be reproduced exactly and stepped through in a debugger.
+//!
+//! The errors of a fallible fold function are handed to the error handler right after the item
+//! that caused them, so the items processed before an abort are the same on every run.
+
+use super::affinity::Placement;
+use super::assign::ShardAssigner;
+use super::fallible::{self, ErrorStream, OnError};
+use super::metrics::ShardMetrics;
+use super::outcome::{Outcome, Quarantine, Quarantined};
+use super::{RuntimeError, Shardable, fold_item};
+use std::marker::PhantomData;
+use std::ops::ControlFlow;
+use std::sync::mpsc::channel;
+use std::time::Instant;
+
+/// A sharded runtime without threads, running all shards on the calling thread in submission
+/// order, see the module documentation.
+pub struct DeterministicRuntime<T: Quarantine, F, S> {
+    shards: Vec<Shard<T, S>>,
+    assigner: Box<dyn ShardAssigner>,
+    func: F,
+    _t: PhantomData<T>,
+}
+
+/// The state of a shard, with the items it quarantined and its metrics
+struct Shard<T: Quarantine, S> {
+    state: S,
+    quarantined: Vec<Quarantined<T::Record>>,
+    metrics: ShardMetrics,
+    idle_since: Instant,
+}
+
+impl<T, F, S> DeterministicRuntime<T, F, S>
+where
+    T: Shardable + Quarantine,
+    F: Fn(&mut S, T),
+    S: Default,
+{
+    /// Create a runtime with one shard per thread of `placement`. The cores of the placement are
+    /// ignored, as no threads are started.
+    pub fn new(
+        placement: impl Into<Placement>,
+        assigner: Box<dyn ShardAssigner>,
+        func: F,
+    ) -> Result<Self, RuntimeError> {
+        let placement = placement.into();
+        if placement.max_threads == 0 {
+            return Err(RuntimeError::NoShards);
+        }
+        let shards = (0..placement.max_threads)
+            .map(|_| Shard {
+                state: S::default(),
+                quarantined: Vec::new(),
+                metrics: ShardMetrics::default(),
+                idle_since: Instant::now(),
+            })
+            .collect();
+        Ok(Self {
+            shards,
+            assigner,
+            func,
+            _t: PhantomData,
+        })
+    }
+
+    /// Fold `item` into the state of its shard before returning. If `func` panics, a record of the
+    /// item is quarantined, see `outcome`.
+    pub fn process_item(&mut self, item: T) {
+        let shard_id = self.assigner.assign(item.shard_key(), self.shards.len());
+        let shard = &mut self.shards[shard_id];
+        shard.metrics.submitted();
+        shard.metrics.received(shard.idle_since.elapsed());
+        shard.idle_since = fold_item(
+            shard_id,
+            &mut shard.state,
+            &self.func,
+            item,
+            &shard.metrics,
+            &mut shard.quarantined,
+        );
+    }
+
+    /// The final states of all shards, their metrics, and the items that were quarantined, like
+    /// `ShardedThreadPerCoreRuntime::finish`. No shard can fail, as no calls are run on them.
+    pub fn finish(self) -> Outcome<S, T::Record> {
+        self.finish_with(|state| state)
+    }
+
+    /// Like `ShardedThreadPerCoreRuntime::finish_with`, but the final states are turned into
+    /// `func(state)` one after the other, on the calling thread.
+    pub fn finish_with<R>(self, func: impl Fn(S) -> R) -> Outcome<R, T::Record> {
+        let mut outcome = Outcome {
+            shards: Vec::with_capacity(self.shards.len()),
+            stats: Vec::with_capacity(self.shards.len()),
+            quarantined: Vec::new(),
+            migrations: Vec::new(),
+            aborted: false,
+        };
+        for shard in self.shards {
+            outcome.stats.push(shard.metrics.stats());
+            outcome.shards.push(Ok(func(shard.state)));
+            outcome.quarantined.extend(shard.quarantined);
+        }
+        outcome
+    }
+
+    /// Fold `items` like `ShardedThreadPerCoreRuntime::try_fold`, with the same errors.
+    pub fn try_fold<R, E: From<RuntimeError>>(
+        placement: impl Into<Placement>,
+        assigner: Box<dyn ShardAssigner>,
+        func: F,
+        finish: impl Fn(S) -> R,
+        items: impl Iterator<Item = Result<T, E>>,
+    ) -> Result<Outcome<R, T::Record>, E> {
+        let mut rt = Self::new(placement, assigner, func)?;
+        rt.submit_all(items, || ControlFlow::Continue(()))?;
+        Ok(rt.finish_with(finish))
+    }
+
+    /// Process `items` until they run out, or until `after_item` breaks. Returns the first error
+    /// of `items`.
+    fn submit_all<E>(
+        &mut self,
+        items: impl Iterator<Item = Result<T, E>>,
+        mut after_item: impl FnMut() -> ControlFlow<()>,
+    ) -> Result<(), E> {
+        for item in items {
+            self.process_item(item?);
+            if after_item().is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The runtime of a fold function `G` returning `Result<(), E>`, see `fallible`.
+impl<T, G, S, E> DeterministicRuntime<T, G, S>
+where
+    T: Shardable + Quarantine + 'static,
+    G: Fn(&mut S, T) -> Result<(), E> + Clone + Send + 'static,
+    S: Default + 'static,
+    E: Send + 'static,
+{
+    /// Fold `items` like `ShardedThreadPerCoreRuntime::try_fold_fallible`. The error of an item is
+    /// handed to `on_error` before the next item is processed.
+    pub fn try_fold_fallible<R, X: From<RuntimeError>>(
+        placement: impl Into<Placement>,
+        assigner: Box<dyn ShardAssigner>,
+        func: G,
+        finish: impl Fn(S) -> R,
+        items: impl Iterator<Item = Result<T, X>>,
+        on_error: impl FnMut(E) -> OnError,
+    ) -> Result<Outcome<R, T::Record>, X> {
+        let (errors_tx, errors) = channel();
+        let func = fallible::reporting(func, errors_tx);
+        let mut rt = DeterministicRuntime::new(placement, assigner, func)?;
+        let mut errors = ErrorStream::new(errors, on_error);
+        let submitted = rt.submit_all(items, || errors.poll());
+        let mut outcome = rt.finish_with(finish);
+        outcome.aborted = errors.finish();
+        submitted?;
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::ShardedThreadPerCoreRuntime;
+    use crate::rt::affinity::CoreList;
+    use crate::rt::assign::Modulo;
+    use std::convert::identity;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Item {
+        key: u64,
+        value: u32,
+    }
+
+    impl Shardable for Item {
+        fn shard_key(&self) -> u64 {
+            self.key
+        }
+    }
+
+    impl Quarantine for Item {
+        type Record = Item;
+        fn record(&self) -> Item {
+            *self
+        }
+    }
+
+    fn items() -> impl Iterator<Item = Result<Item, RuntimeError>> {
+        (0..100).map(|value| {
+            Ok(Item {
+                key: value as u64 % 7,
+                value,
+            })
+        })
+    }
+
+    #[test]
+    fn test_same_outcome_as_threaded() {
+        let fold = |seen: &mut Vec<Item>, item: Item| {
+            assert_ne!(item.value, 50, "Bad item");
+            seen.push(item);
+        };
+        let shards = CoreList::available().unwrap().ids().len().min(3);
+        let deterministic =
+            DeterministicRuntime::try_fold(shards, Box::new(Modulo), fold, identity, items())
+                .unwrap();
+        let threaded = ShardedThreadPerCoreRuntime::try_fold(
+            shards,
+            Box::new(Modulo),
+            fold,
+            identity,
+            items(),
+        )
+        .unwrap();
+        assert_eq!(deterministic.shards, threaded.shards);
+        assert_eq!(deterministic.quarantined, threaded.quarantined);
+        assert_eq!(deterministic.quarantined[0].item.value, 50);
+        let states = deterministic.states().unwrap();
+        assert_eq!(states.len(), shards);
+        assert!(
+            states[0]
+                .iter()
+                .all(|item| (item.key as usize).is_multiple_of(shards))
+        );
+        assert!(states[0].is_sorted_by_key(|item| item.value));
+        assert_eq!(states.iter().map(Vec::len).sum::<usize>(), 99);
+    }
+
+    #[test]
+    fn test_fallible() {
+        let fold = |sum: &mut u32, item: Item| match item.value {
+            value if value % 10 == 9 => Err(value),
+            value => {
+                *sum += value;
+                Ok(())
+            }
+        };
+        let mut rejected = Vec::new();
+        let outcome = DeterministicRuntime::try_fold_fallible(
+            2,
+            Box::new(Modulo),
+            fold,
+            identity,
+            items(),
+            |value| {
+                rejected.push(value);
+                match value {
+                    29 => OnError::Abort,
+                    _ => OnError::Continue,
+                }
+            },
+        )
+        .unwrap();
+        // the run stops right after the item the handler aborted on, every time
+        assert!(outcome.aborted);
+        assert_eq!(rejected, [9, 19, 29]);
+        let sum = outcome.states().unwrap().into_iter().sum::<u32>();
+        assert_eq!(sum, (0..30).sum::<u32>() - 9 - 19 - 29);
+        assert!(matches!(
+            DeterministicRuntime::try_fold(
+                0,
+                Box::new(Modulo),
+                |_: &mut u32, _| {},
+                identity,
+                items()
+            ),
+            Err(RuntimeError::NoShards)
+        ));
+    }
+}
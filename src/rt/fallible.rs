@@ -0,0 +1,112 @@
+//! Fold functions that can fail, see `ShardedThreadPerCoreRuntime::try_fold_fallible`.
+//!
+//! The errors returned on the shards are sent back to the thread submitting the items, which hands
+//! them to an error handler between two items. The handler decides whether to keep going, or to
+//! stop submitting items and finish the run early. The errors of one key arrive in the order its
+//! items were processed, even if the key moved between shards.
+//!
+//! Errors of the items themselves stop a `try_fold` at the first one, unless they are skipped with
+//! `skip_errors`.
+
+use std::ops::ControlFlow;
+use std::sync::mpsc::{Receiver, Sender};
+
+/// What to do after a fold function returned an error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnError {
+    /// Keep submitting items
+    Continue,
+    /// Stop submitting items, and finish the items already submitted
+    Abort,
+}
+
+/// Wrap a fallible fold function into one the runtime can run, which sends its errors to `errors`.
+pub(super) fn reporting<S: 'static, T: 'static, E: Send + 'static>(
+    func: impl Fn(&mut S, T) -> Result<(), E> + Clone + Send + 'static,
+    errors: Sender<E>,
+) -> impl Fn(&mut S, T) + Clone + Send + 'static {
+    move |state, item| {
+        if let Err(e) = func(state, item) {
+            // the receiver lives until the runtime finished, so this can't fail
+            let _ = errors.send(e);
+        }
+    }
+}
+
+/// The errors sent back by the shards, with the handler deciding what to do about them
+pub(super) struct ErrorStream<E, H> {
+    errors: Receiver<E>,
+    on_error: H,
+    aborted: bool,
+}
+
+impl<E, H: FnMut(E) -> OnError> ErrorStream<E, H> {
+    pub(super) fn new(errors: Receiver<E>, on_error: H) -> Self {
+        Self {
+            errors,
+            on_error,
+            aborted: false,
+        }
+    }
+
+    /// Hand the errors received so far to the handler, and break once it aborts.
+    pub(super) fn poll(&mut self) -> ControlFlow<()> {
+        while let Ok(e) = self.errors.try_recv() {
+            if (self.on_error)(e) == OnError::Abort {
+                self.aborted = true;
+                return ControlFlow::Break(());
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Hand the remaining errors to the handler once the runtime finished, and return whether it
+    /// aborted at any point. Every error is handed over, including the ones of items that were
+    /// already submitted when the handler aborted.
+    pub(super) fn finish(mut self) -> bool {
+        for e in self.errors.try_iter() {
+            self.aborted |= (self.on_error)(e) == OnError::Abort;
+        }
+        self.aborted
+    }
+}
+
+/// Skip the first `budget` errors of `items` that are `skippable`, handing each to `on_skip`. The
+/// other errors, and the errors over the budget, are yielded as usual, so a
+/// `ShardedThreadPerCoreRuntime::try_fold` stops at them.
+pub fn skip_errors<T, E>(
+    items: impl Iterator<Item = Result<T, E>>,
+    mut budget: u64,
+    skippable: impl Fn(&E) -> bool,
+    mut on_skip: impl FnMut(E),
+) -> impl Iterator<Item = Result<T, E>> {
+    items.filter_map(move |item| match item {
+        Err(e) if budget > 0 && skippable(&e) => {
+            budget -= 1;
+            on_skip(e);
+            None
+        }
+        item => Some(item),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_errors() {
+        let items = [Ok(1), Err("a"), Ok(2), Err("b"), Err("c"), Ok(3)];
+        let mut skipped = Vec::new();
+        let result =
+            skip_errors(items.into_iter(), 2, |_| true, |e| skipped.push(e)).collect::<Vec<_>>();
+        assert_eq!(result, [Ok(1), Ok(2), Err("c"), Ok(3)]);
+        assert_eq!(skipped, ["a", "b"]);
+
+        let mut skipped = Vec::new();
+        let result = skip_errors(items.into_iter(), 5, |e| *e != "b", |e| skipped.push(e))
+            .collect::<Vec<_>>();
+        assert_eq!(result, [Ok(1), Ok(2), Err("b"), Ok(3)]);
+        assert_eq!(skipped, ["a", "c"]);
+    }
+}
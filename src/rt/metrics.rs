@@ -0,0 +1,287 @@
+//! Per-shard metrics of a `ShardedThreadPerCoreRuntime`.
+//!
+//! Every shard has a `ShardMetrics` of atomic counters, shared between the shard thread and the
+//! producers. Only relaxed atomic operations are used, so reading the metrics while the runtime is
+//! running gives a recent, but not necessarily consistent, view. After the runtime finished, the
+//! metrics are exact.
+
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::time::Duration;
+
+/// The number of buckets of a latency `Histogram`. Bucket `i` counts latencies of `2^i` up to
+/// `2^(i + 1)` nanoseconds, and the last bucket also counts anything longer, so the histogram
+/// resolves latencies up to about 4 seconds.
+pub const LATENCY_BUCKETS: usize = 32;
+
+/// The number of recent item keys sampled by a shard, see `ShardMetrics::sampled_keys`.
+pub const KEY_SAMPLES: usize = 32;
+
+/// A shard samples the key of one in this many items.
+const SAMPLE_EVERY: u64 = 16;
+
+/// The live counters of a shard.
+#[derive(Default)]
+pub struct ShardMetrics {
+    /// The NUMA node of the shard, if known
+    node: Option<usize>,
+    /// Whether the messages are submitted from another NUMA node, if known, see `super::numa`
+    remote: Option<bool>,
+    // written by the producers
+    submitted: AtomicU64,
+    remote_messages: AtomicU64,
+    queue_high_water_mark: AtomicU64,
+    // written by the shard thread
+    received: AtomicU64,
+    items: AtomicU64,
+    panics: AtomicU64,
+    busy_nanos: AtomicU64,
+    idle_nanos: AtomicU64,
+    latency: [AtomicU64; LATENCY_BUCKETS],
+    key_samples: [AtomicU64; KEY_SAMPLES],
+    failed: AtomicBool,
+}
+
+impl ShardMetrics {
+    /// The metrics of a shard on NUMA node `node`, whose messages are submitted from threads on
+    /// `producer_node`.
+    pub(super) fn new(node: Option<usize>, producer_node: Option<usize>) -> Self {
+        Self {
+            node,
+            remote: node
+                .zip(producer_node)
+                .map(|(node, producer)| node != producer),
+            ..Self::default()
+        }
+    }
+
+    /// Count a message submitted to the shard, updating the queue depth high-water mark.
+    pub(super) fn submitted(&self) {
+        if self.remote == Some(true) {
+            self.remote_messages.fetch_add(1, Relaxed);
+        }
+        let submitted = self.submitted.fetch_add(1, Relaxed) + 1;
+        let depth = submitted.saturating_sub(self.received.load(Relaxed));
+        if depth > self.queue_high_water_mark.load(Relaxed) {
+            self.queue_high_water_mark.fetch_max(depth, Relaxed);
+        }
+    }
+
+    /// Count a message received by the shard, after waiting `idle` for it.
+    pub(super) fn received(&self, idle: Duration) {
+        self.received.fetch_add(1, Relaxed);
+        self.idle_nanos.fetch_add(idle.as_nanos() as u64, Relaxed);
+    }
+
+    /// Count an item with shard key `key` that took `busy` to process.
+    pub(super) fn processed_item(&self, key: u64, busy: Duration) {
+        let items = self.items.fetch_add(1, Relaxed);
+        if items.is_multiple_of(SAMPLE_EVERY) {
+            let sample = (items / SAMPLE_EVERY) as usize % KEY_SAMPLES;
+            self.key_samples[sample].store(key, Relaxed);
+        }
+        self.latency[bucket(busy)].fetch_add(1, Relaxed);
+        self.busy(busy);
+    }
+
+    /// Count an item whose processing panicked, see `super::outcome`.
+    pub(super) fn panicked(&self) {
+        self.panics.fetch_add(1, Relaxed);
+    }
+
+    /// Count time spent on other messages than items, like `ShardedThreadPerCoreRuntime::call`.
+    pub(super) fn busy(&self, busy: Duration) {
+        self.busy_nanos.fetch_add(busy.as_nanos() as u64, Relaxed);
+    }
+
+    /// Mark the shard as failed, see `super::outcome`.
+    pub(super) fn failed(&self) {
+        self.failed.store(true, Relaxed);
+    }
+
+    /// Whether the shard failed, after which it drops its calls and quarantines its items.
+    pub fn is_failed(&self) -> bool {
+        self.failed.load(Relaxed)
+    }
+
+    /// Read the current value of the counters.
+    pub fn stats(&self) -> ShardStats {
+        let received = self.received.load(Relaxed);
+        ShardStats {
+            node: self.node,
+            remote_messages: self.remote.map(|_| self.remote_messages.load(Relaxed)),
+            items: self.items.load(Relaxed),
+            panics: self.panics.load(Relaxed),
+            queue_depth: self.submitted.load(Relaxed).saturating_sub(received),
+            queue_high_water_mark: self.queue_high_water_mark.load(Relaxed),
+            busy: Duration::from_nanos(self.busy_nanos.load(Relaxed)),
+            idle: Duration::from_nanos(self.idle_nanos.load(Relaxed)),
+            latency: Histogram {
+                counts: self.latency.each_ref().map(|count| count.load(Relaxed)),
+            },
+            failed: self.is_failed(),
+        }
+    }
+
+    /// The keys of up to `KEY_SAMPLES` items processed recently, which shows the keys that make up
+    /// most of the load of the shard.
+    pub fn sampled_keys(&self) -> Vec<u64> {
+        let samples = self.items.load(Relaxed).div_ceil(SAMPLE_EVERY) as usize;
+        self.key_samples[..samples.min(KEY_SAMPLES)]
+            .iter()
+            .map(|key| key.load(Relaxed))
+            .collect()
+    }
+}
+
+fn bucket(latency: Duration) -> usize {
+    let nanos = latency.as_nanos().max(1);
+    (nanos.ilog2() as usize).min(LATENCY_BUCKETS - 1)
+}
+
+/// The metrics of a shard at one point in time, see `ShardMetrics::stats`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShardStats {
+    /// The NUMA node the shard runs on, if known
+    pub node: Option<usize>,
+    /// The number of messages submitted from another NUMA node than the one of the shard, if the
+    /// node of the shard and of the submitting threads are known, see `super::numa`
+    pub remote_messages: Option<u64>,
+    /// The number of items processed, including the items that panicked
+    pub items: u64,
+    /// The number of items whose processing panicked
+    pub panics: u64,
+    /// The number of messages waiting in the queue of the shard
+    pub queue_depth: u64,
+    /// The highest number of messages that were waiting in the queue at once
+    pub queue_high_water_mark: u64,
+    /// Time spent processing items and other messages
+    pub busy: Duration,
+    /// Time spent waiting for messages
+    pub idle: Duration,
+    /// The time it took to process each item
+    pub latency: Histogram,
+    /// Whether the shard failed, see `super::outcome`
+    pub failed: bool,
+}
+
+impl ShardStats {
+    /// The fraction of time the shard was busy, between 0 and 1.
+    pub fn utilization(&self) -> f64 {
+        let total = self.busy + self.idle;
+        if total.is_zero() {
+            0.0
+        } else {
+            self.busy.as_secs_f64() / total.as_secs_f64()
+        }
+    }
+}
+
+/// A histogram of latencies with logarithmic buckets, see `LATENCY_BUCKETS`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    counts: [u64; LATENCY_BUCKETS],
+}
+
+impl Histogram {
+    /// The number of latencies counted.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The upper bound of every bucket, with the number of latencies in it.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        let upper_bounds = (1..=LATENCY_BUCKETS as u32).map(|i| Duration::from_nanos(1 << i));
+        upper_bounds.zip(self.counts.iter().copied())
+    }
+
+    /// An upper bound of the `q` quantile, e.g. 0.99 for the 99th percentile, or `None` if the
+    /// histogram is empty. The bound is exact up to the factor 2 of the bucket width.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((q * count as f64).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        self.buckets()
+            .find(|(_, bucket_count)| {
+                seen += bucket_count;
+                seen >= rank
+            })
+            .map(|(upper_bound, _)| upper_bound)
+    }
+
+    /// Add the counts of `other` to this histogram, e.g. to combine the histograms of all shards.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let metrics = ShardMetrics::default();
+        for nanos in [0, 1, 3, 100, 100, 100, 100, 100, 100, 5000] {
+            metrics.processed_item(0, Duration::from_nanos(nanos));
+        }
+        metrics.processed_item(0, Duration::from_secs(60));
+        let latency = metrics.stats().latency;
+        assert_eq!(latency.count(), 11);
+        assert_eq!(latency.quantile(0.0), Some(Duration::from_nanos(2)));
+        assert_eq!(latency.quantile(0.5), Some(Duration::from_nanos(128)));
+        assert_eq!(latency.quantile(0.9), Some(Duration::from_nanos(8192)));
+        assert_eq!(latency.quantile(1.0), Some(Duration::from_nanos(1 << 32)));
+        assert_eq!(Histogram::default().quantile(0.5), None);
+    }
+
+    #[test]
+    fn test_queue_depth() {
+        let metrics = ShardMetrics::default();
+        for _ in 0..3 {
+            metrics.submitted();
+        }
+        metrics.received(Duration::from_millis(3));
+        metrics.processed_item(0, Duration::from_millis(1));
+        metrics.submitted();
+        let stats = metrics.stats();
+        assert_eq!(stats.queue_depth, 3);
+        assert_eq!(stats.queue_high_water_mark, 3);
+        assert_eq!(stats.items, 1);
+        assert_eq!(stats.utilization(), 0.25);
+    }
+
+    #[test]
+    fn test_remote_messages() {
+        let remote = ShardMetrics::new(Some(1), Some(0));
+        let local = ShardMetrics::new(Some(0), Some(0));
+        let unknown = ShardMetrics::new(Some(1), None);
+        for metrics in [&remote, &local, &unknown] {
+            metrics.submitted();
+            metrics.submitted();
+        }
+        assert_eq!(remote.stats().remote_messages, Some(2));
+        assert_eq!(local.stats().remote_messages, Some(0));
+        assert_eq!(unknown.stats().remote_messages, None);
+        assert_eq!(unknown.stats().node, Some(1));
+    }
+
+    #[test]
+    fn test_sampled_keys() {
+        let metrics = ShardMetrics::default();
+        assert!(metrics.sampled_keys().is_empty());
+        for key in 0..SAMPLE_EVERY * 2 + 1 {
+            metrics.processed_item(key, Duration::ZERO);
+        }
+        assert_eq!(metrics.sampled_keys(), [0, SAMPLE_EVERY, SAMPLE_EVERY * 2]);
+        for _ in 0..SAMPLE_EVERY * KEY_SAMPLES as u64 {
+            metrics.processed_item(7, Duration::ZERO);
+        }
+        assert_eq!(metrics.sampled_keys(), [7; KEY_SAMPLES]);
+    }
+}
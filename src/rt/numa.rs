@@ -0,0 +1,116 @@
+//! The NUMA topology of the machine, read from sysfs.
+//!
+//! On a machine with several NUMA nodes, like a dual-socket server, every node has its own memory,
+//! and accessing the memory of another node is slower. The runtime spreads the shards evenly over
+//! the nodes, see `affinity::Placement::shard_cores`.
+//!
+//! Linux allocates a page on the node of the thread that first touches it. The state of a shard is
+//! created on the shard thread after it is pinned to its core, and only grows there, so it lives on
+//! the node of the shard. The items however are allocated by the thread submitting them, and cross
+//! nodes when their shard runs on another node, which `metrics::ShardStats::remote_messages`
+//! counts. The state of a key moved to a shard on another node crosses nodes as well, see
+//! `rebalance`.
+
+use super::affinity::CoreList;
+use std::path::Path;
+
+/// Where the nodes of the machine are listed, as directories named `node<id>` with a `cpulist`
+const SYSFS_NODES: &str = "/sys/devices/system/node";
+
+/// The NUMA nodes of the machine, with their cores
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Topology {
+    /// The nodes with at least one core, ordered by node id
+    nodes: Vec<(usize, CoreList)>,
+}
+
+impl Topology {
+    /// The topology of this machine. Without NUMA information in sysfs, like on other operating
+    /// systems than Linux, the topology has no nodes, and every core is on an unknown node.
+    pub fn discover() -> Self {
+        Self::read(Path::new(SYSFS_NODES)).unwrap_or_default()
+    }
+
+    /// Read the topology from a sysfs node directory.
+    fn read(dir: &Path) -> std::io::Result<Self> {
+        let mut nodes = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(id) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|id| id.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            let cpulist = std::fs::read_to_string(entry.path().join("cpulist"))?;
+            // nodes with memory, but without cores, have an empty list
+            if let Ok(cores) = cpulist.trim().parse::<CoreList>() {
+                nodes.push((id, cores));
+            }
+        }
+        nodes.sort_unstable_by_key(|&(id, _)| id);
+        Ok(Self { nodes })
+    }
+
+    /// The node of `core`, if it is known.
+    pub fn node_of(&self, core: usize) -> Option<usize> {
+        self.nodes
+            .iter()
+            .find(|(_, cores)| cores.ids().contains(&core))
+            .map(|&(id, _)| id)
+    }
+
+    /// The number of nodes with cores, which is 0 if the topology is unknown.
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Order `cores` so consecutive shards alternate between the nodes, keeping the order of the
+    /// cores within each node. Any number of shards taken from the front is then spread as evenly
+    /// as possible over the nodes. Cores on an unknown node come last.
+    pub fn interleave(&self, cores: &[usize]) -> Vec<usize> {
+        let mut per_node = vec![Vec::new(); self.nodes.len() + 1];
+        for &core in cores {
+            let index = self
+                .nodes
+                .iter()
+                .position(|(_, node_cores)| node_cores.ids().contains(&core))
+                .unwrap_or(self.nodes.len());
+            per_node[index].push(core);
+        }
+        let unknown = per_node.pop().unwrap_or_default();
+        let mut interleaved = Vec::with_capacity(cores.len());
+        for round in 0..per_node.iter().map(Vec::len).max().unwrap_or(0) {
+            interleaved.extend(per_node.iter().filter_map(|cores| cores.get(round)));
+        }
+        interleaved.extend(unknown);
+        interleaved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topology() {
+        let dir = std::env::temp_dir().join(format!("ktht-{}-numa", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (node, cpulist) in [("node0", "0-2\n"), ("node1", "4-5\n"), ("node2", "\n")] {
+            std::fs::create_dir_all(dir.join(node)).unwrap();
+            std::fs::write(dir.join(node).join("cpulist"), cpulist).unwrap();
+        }
+        std::fs::write(dir.join("online"), "0-2\n").unwrap();
+
+        let topology = Topology::read(&dir).unwrap();
+        assert_eq!(topology.num_nodes(), 2);
+        assert_eq!(topology.node_of(1), Some(0));
+        assert_eq!(topology.node_of(5), Some(1));
+        assert_eq!(topology.node_of(3), None);
+        assert_eq!(topology.interleave(&[0, 1, 2, 3, 4, 5]), [0, 4, 1, 5, 2, 3]);
+        assert_eq!(Topology::default().interleave(&[2, 1]), [2, 1]);
+        assert!(Topology::read(&dir.join("missing")).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! What a `ShardedThreadPerCoreRuntime` reports when it finishes, including the incidents that
+//! happened while it ran.
+//!
+//! A panic while processing an item is caught on the shard, which records the item in quarantine
+//! and keeps processing the next items. The state of the shard is kept as the panic left it, so
+//! an item should not panic halfway through changing it. The same goes for a panic in a
+//! `ShardedThreadPerCoreRuntime::call`, which is returned to the caller. A panic anywhere else,
+//! like while moving a key between shards, stops the shard thread: the shard is failed, its state
+//! is lost, and the items submitted to it afterwards are quarantined as well.
+
+use super::metrics::ShardStats;
+use super::rebalance::Migration;
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+
+/// Allows an item to be quarantined when processing it panics. The item itself is consumed by the
+/// processing function, so a record of it is taken before.
+pub trait Quarantine {
+    /// What is kept of a quarantined item
+    type Record: Send + 'static;
+
+    fn record(&self) -> Self::Record;
+}
+
+/// An item that was not processed, because processing it panicked or its shard failed
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quarantined<R> {
+    /// The shard the item was submitted to
+    pub shard: usize,
+    pub item: R,
+    /// The panic message
+    pub message: String,
+}
+
+/// A shard whose thread stopped with a panic
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShardFailure {
+    pub shard: usize,
+    /// The panic message
+    pub message: String,
+}
+
+impl ShardFailure {
+    /// The failure of a shard that dropped a call, which doesn't tell why it failed. That is only
+    /// reported once the runtime is finished.
+    pub(super) fn dropped_call(shard: usize) -> Self {
+        ShardFailure {
+            shard,
+            message: "the shard failed".to_string(),
+        }
+    }
+}
+
+impl Display for ShardFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "shard {} failed: {}", self.shard, self.message)
+    }
+}
+
+impl std::error::Error for ShardFailure {}
+
+/// The outcome of a run, see `ShardedThreadPerCoreRuntime::finish`.
+pub struct Outcome<S, R> {
+    /// The final state of every shard, ordered by shard id, or why the shard failed
+    pub shards: Vec<Result<S, ShardFailure>>,
+    /// The final metrics of every shard, ordered by shard id
+    pub stats: Vec<ShardStats>,
+    /// The items that were not processed, by shard, in the order they were submitted
+    pub quarantined: Vec<Quarantined<R>>,
+    /// The keys moved between shards, in the order they were moved, see
+    /// `ShardedThreadPerCoreRuntime::rebalance`
+    pub migrations: Vec<Migration>,
+    /// Whether an error handler aborted the run, in which case the items after the error may not
+    /// have been submitted, see `ShardedThreadPerCoreRuntime::try_fold_fallible`
+    pub aborted: bool,
+}
+
+impl<S, R> Outcome<S, R> {
+    /// The shards that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &ShardFailure> {
+        self.shards.iter().filter_map(|shard| shard.as_ref().err())
+    }
+
+    /// The final state of every shard, or the first failure if any shard failed.
+    pub fn states(self) -> Result<Vec<S>, ShardFailure> {
+        self.shards.into_iter().collect()
+    }
+}
+
+/// The message of a caught panic, which is a string unless the panic was raised with another
+/// payload by `std::panic::panic_any`.
+pub(super) fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
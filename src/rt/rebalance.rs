@@ -0,0 +1,153 @@
+//! Moving keys between the shards of a running `ShardedThreadPerCoreRuntime` to even out their
+//! load, see `ShardedThreadPerCoreRuntime::rebalance`.
+//!
+//! A migration moves the state of one key from its shard to another, and routes the items of the
+//! key submitted afterwards to the new shard. To keep the items of the key in order, the old shard
+//! takes the state out when it has processed the items submitted before the migration, and the
+//! items of the key submitted meanwhile are held back until the state is queued on the new shard
+//! ahead of them. Both shards keep processing their other keys, but the moved key waits until the
+//! old shard has drained, which is why keys are moved away from the busiest shard early.
+
+use super::metrics::ShardStats;
+use fnv::FnvHashMap;
+
+/// A shard state from which the state of a single key can be moved to another shard.
+pub trait Migrate {
+    /// The state of one key
+    type Part: Send + 'static;
+
+    /// Remove the state of `key`, if there is any.
+    fn take(&mut self, key: u64) -> Option<Self::Part>;
+
+    /// Insert the state of `key` taken from another shard.
+    fn put(&mut self, key: u64, part: Self::Part);
+}
+
+/// A key moved from one shard to another
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Migration {
+    pub key: u64,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// When to move keys between shards, based on the queue depth of the shards.
+#[derive(Clone, Copy, Debug)]
+pub struct RebalancePolicy {
+    /// The busiest shard is only relieved once this many messages wait in its queue.
+    pub min_queue_depth: u64,
+    /// How many times deeper the queue of the busiest shard must be than the queue of the least
+    /// busy shard.
+    pub imbalance: f64,
+}
+
+impl Default for RebalancePolicy {
+    fn default() -> Self {
+        Self {
+            min_queue_depth: 1024,
+            imbalance: 2.0,
+        }
+    }
+}
+
+impl RebalancePolicy {
+    /// Pick a key to move from the busiest shard to the least busy one, if they are imbalanced.
+    /// Failed shards are left out, as their queues drain without processing anything.
+    ///
+    /// `sampled_keys` are the recent keys of the busiest shard, which are assumed to make up its
+    /// queue in the same proportions, and `shard_of` returns the current shard of a key. The
+    /// busiest key whose estimated load fits in half the difference between both queues is picked,
+    /// so a key that carries most of the load by itself stays put, instead of saturating the other
+    /// shard; the keys sharing its shard are moved away instead.
+    pub fn plan(
+        &self,
+        stats: &[ShardStats],
+        sampled_keys: impl Fn(usize) -> Vec<u64>,
+        shard_of: impl Fn(u64) -> usize,
+    ) -> Option<Migration> {
+        let running = || stats.iter().enumerate().filter(|(_, s)| !s.failed);
+        let (from, busiest) = running().max_by_key(|(_, s)| s.queue_depth)?;
+        let (to, idlest) = running().min_by_key(|(_, s)| s.queue_depth)?;
+        let (busiest, idlest) = (busiest.queue_depth, idlest.queue_depth);
+        if busiest < self.min_queue_depth || (busiest as f64) < self.imbalance * (idlest + 1) as f64
+        {
+            return None;
+        }
+
+        let samples = sampled_keys(from);
+        let mut counts = FnvHashMap::<u64, u64>::default();
+        for &key in &samples {
+            *counts.entry(key).or_default() += 1;
+        }
+        let headroom = (busiest - idlest) as f64 / 2.0;
+        counts
+            .into_iter()
+            .map(|(key, count)| (key, busiest as f64 * count as f64 / samples.len() as f64))
+            .filter(|&(key, load)| load <= headroom && shard_of(key) == from)
+            .max_by(|(key_a, a), (key_b, b)| a.total_cmp(b).then(key_b.cmp(key_a)))
+            .map(|(key, _)| Migration { key, from, to })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue_depths(depths: &[u64]) -> Vec<ShardStats> {
+        depths
+            .iter()
+            .map(|&queue_depth| ShardStats {
+                queue_depth,
+                ..ShardStats::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan() {
+        let policy = RebalancePolicy {
+            min_queue_depth: 100,
+            imbalance: 2.0,
+        };
+        // key 1 carries three quarters of the load of shard 1, and keys 3 and 5 an eighth each
+        let samples = |shard| {
+            assert_eq!(shard, 1);
+            vec![1, 3, 1, 1, 5, 1, 1, 1]
+        };
+        let shard_of = |key| key as usize % 2;
+
+        assert_eq!(
+            policy.plan(&queue_depths(&[10, 90]), samples, shard_of),
+            None
+        );
+        assert_eq!(
+            policy.plan(&queue_depths(&[120, 200]), samples, shard_of),
+            None
+        );
+        // moving key 1 would make shard 0 the busiest
+        let migration = policy.plan(&queue_depths(&[0, 200]), samples, shard_of);
+        assert_eq!(
+            migration,
+            Some(Migration {
+                key: 3,
+                from: 1,
+                to: 0
+            })
+        );
+        // keys already moved elsewhere are skipped
+        let shard_of = |key| if key == 3 { 0 } else { key as usize % 2 };
+        let migration = policy.plan(&queue_depths(&[0, 200]), samples, shard_of);
+        assert_eq!(
+            migration,
+            Some(Migration {
+                key: 5,
+                from: 1,
+                to: 0
+            })
+        );
+        // a failed shard drains its queue, but takes no keys
+        let mut stats = queue_depths(&[0, 200]);
+        stats[0].failed = true;
+        assert_eq!(policy.plan(&stats, samples, shard_of), None);
+    }
+}
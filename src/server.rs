@@ -0,0 +1,491 @@
+//! A long-running service applying transactions streamed over TCP or Unix sockets.
+//!
+//! Clients send csv transaction lines without a header, in the columns `type, client, tx, amount`,
+//! e.g. `deposit, 1, 1, 1.0`. Every line gets a response line, in the order the lines were sent:
+//! - `ok` if the transaction was applied
+//! - `error,<code>` if it was rejected, where `<code>` is a `TransactionError::code`
+//! - `error,internal` if processing it panicked, in which case it is quarantined, see
+//!   `crate::rt::outcome`
+//! - `invalid,<message>` if the line is not a valid transaction
+//!
+//! Besides transactions, clients can query the live accounts:
+//! - `balance,<client>` is answered with `balance,<client>,<available>,<held>,<total>,<locked>`,
+//!   or `error,client_not_found` if the client has no account
+//! - `accounts` is answered with `accounts,<n>`, followed by `n` lines of
+//!   `<client>,<available>,<held>,<total>,<locked>` ordered by client id. The accounts are a
+//!   consistent snapshot across shards, see `ShardedThreadPerCoreRuntime::snapshot`.
+//!
+//! - `stats` is answered with `stats,<n>`, followed by a line per shard of
+//!   `<shard>,<items>,<queue depth>,<queue high-water mark>,<busy ns>,<idle ns>,<p50 ns>,<p99 ns>`,
+//!   where the latency quantiles are upper bounds, see `crate::rt::metrics`
+//!
+//! A query is answered with `error,internal` if a shard it needs failed, see `crate::rt::outcome`.
+//! A query sees every transaction sent before it over the same connection.
+//!
+//! All connections share one `ShardedThreadPerCoreRuntime`, which lives as long as the server, so
+//! the accounts outlive the connections that changed them. Transactions of a client sent over one
+//! connection are applied in order; there is no ordering between connections. With the `http`
+//! feature, the same runtime can also be served over HTTP, see `http`.
+
+use crate::account::{Account, Accounts, ClientId, TransactionError};
+use crate::io::{AccountRecord, CsvTransaction, CsvTransactionType, csv_line_reader};
+use crate::rt::affinity::Placement;
+use crate::rt::assign::ShardAssigner;
+use crate::rt::outcome::Quarantine;
+use crate::rt::rebalance::{Migrate, Migration, RebalancePolicy};
+use crate::rt::{RuntimeError, Shardable, ShardedThreadPerCoreRuntime};
+use std::fmt::Write as _;
+use std::io::{BufWriter, Read, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError, channel};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+#[cfg(feature = "http")]
+pub mod http;
+pub mod metrics;
+
+/// A transaction submitted to the runtime, with the channel its outcome is sent back on.
+pub struct Request {
+    tx: CsvTransaction,
+    reply: Sender<Result<(), TransactionError>>,
+}
+
+impl Shardable for Request {
+    fn shard_key(&self) -> u64 {
+        self.tx.shard_key()
+    }
+}
+
+/// The reply channel is dropped with a quarantined request, so its connection is told it failed.
+impl Quarantine for Request {
+    type Record = CsvTransaction;
+
+    fn record(&self) -> CsvTransaction {
+        self.tx.record()
+    }
+}
+
+/// The state of a shard of the server
+#[derive(Default)]
+pub struct Shard {
+    pub accounts: Accounts,
+    pub counters: Counters,
+}
+
+/// Business counters of a shard. They are only changed by the shard thread itself, and read with
+/// `ShardedThreadPerCoreRuntime::snapshot`, so counting needs no synchronization.
+///
+/// Besides counts, they keep gauges of the accounts of the shard, which are updated with every
+/// transaction and migration so reading them doesn't take a pass over the accounts.
+#[derive(Clone, Default)]
+pub struct Counters {
+    /// Transactions submitted, indexed by `CsvTransactionType`
+    pub transactions: [u64; CsvTransactionType::ALL.len()],
+    /// Transactions rejected, indexed by `TransactionError`
+    pub rejections: [u64; TransactionError::ALL.len()],
+    pub accounts: u64,
+    /// Accounts locked by a chargeback
+    pub locked_accounts: u64,
+    /// Funds held by disputes, over all accounts
+    pub held_funds: f64,
+}
+
+impl Counters {
+    /// Add `account` to the gauges.
+    fn add_account(&mut self, account: &Account) {
+        self.accounts += 1;
+        self.locked_accounts += account.is_locked() as u64;
+        self.held_funds += account.held();
+    }
+
+    /// Remove `account` from the gauges, e.g. before it changes.
+    fn remove_account(&mut self, account: &Account) {
+        self.accounts -= 1;
+        self.locked_accounts -= account.is_locked() as u64;
+        self.held_funds -= account.held();
+    }
+
+    fn count(&mut self, tx_type: CsvTransactionType, result: Result<(), TransactionError>) {
+        self.transactions[tx_type as usize] += 1;
+        if let Err(e) = result {
+            self.rejections[e as usize] += 1;
+        }
+    }
+
+    /// Add the counts of `other`, e.g. to combine the counters of all shards.
+    pub fn merge(&mut self, other: &Counters) {
+        for (count, other) in self.transactions.iter_mut().zip(other.transactions) {
+            *count += other;
+        }
+        for (count, other) in self.rejections.iter_mut().zip(other.rejections) {
+            *count += other;
+        }
+        self.accounts += other.accounts;
+        self.locked_accounts += other.locked_accounts;
+        self.held_funds += other.held_funds;
+    }
+}
+
+/// How often the shards are checked for imbalanced queues, see `rebalance`.
+const REBALANCE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub type Runtime = ShardedThreadPerCoreRuntime<Request, fn(&mut Shard, Request), Shard>;
+
+/// Start a runtime for the server with the shards of `placement`, routing clients with `assigner`.
+/// Only a `migratable` runtime can be passed to `rebalance`.
+pub fn runtime(
+    placement: impl Into<Placement>,
+    assigner: Box<dyn ShardAssigner>,
+    migratable: bool,
+) -> Result<Arc<Runtime>, RuntimeError> {
+    let runtime = if migratable {
+        Runtime::new_migratable(placement, assigner, apply)?
+    } else {
+        Runtime::new(placement, assigner, apply)?
+    };
+    Ok(Arc::new(runtime))
+}
+
+fn apply(shard: &mut Shard, request: Request) {
+    let client_id = request.tx.client();
+    if let Some(account) = shard.accounts.get(client_id) {
+        shard.counters.remove_account(account);
+    }
+    let result = request.tx.execute_transaction(&mut shard.accounts);
+    if let Some(account) = shard.accounts.get(client_id) {
+        shard.counters.add_account(account);
+    }
+    shard.counters.count(request.tx.tx_type(), result);
+    // the connection may be gone by now, in which case nobody is waiting for the outcome
+    let _ = request.reply.send(result);
+}
+
+impl Migrate for Shard {
+    type Part = Account;
+
+    fn take(&mut self, key: u64) -> Option<Account> {
+        let account = self.accounts.take(key)?;
+        self.counters.remove_account(&account);
+        Some(account)
+    }
+
+    fn put(&mut self, key: u64, account: Account) {
+        self.counters.add_account(&account);
+        self.accounts.put(key, account);
+    }
+}
+
+/// Move clients from the busiest shard to the least busy one forever, checking the queues of the
+/// shards every `REBALANCE_INTERVAL`, see `ShardedThreadPerCoreRuntime::rebalance`. The runtime
+/// must be `migratable`, see `runtime`.
+pub fn rebalance(runtime: Arc<Runtime>, policy: RebalancePolicy) {
+    loop {
+        sleep(REBALANCE_INTERVAL);
+        if let Some(Migration { key, from, to }) = runtime.rebalance(&policy) {
+            eprintln!("moved client {key} from shard {from} to shard {to}");
+        }
+    }
+}
+
+/// Accept TCP connections forever, serving each one on its own thread.
+pub fn serve_tcp(listener: TcpListener, runtime: Arc<Runtime>) {
+    for stream in listener.incoming() {
+        let stream = stream.and_then(|stream| Ok((stream.try_clone()?, stream)));
+        match stream {
+            Ok((reader, writer)) => spawn_connection(reader, writer, runtime.clone()),
+            Err(e) => eprintln!("could not accept connection: {e}"),
+        }
+    }
+}
+
+/// Accept Unix socket connections forever, serving each one on its own thread.
+#[cfg(unix)]
+pub fn serve_unix(listener: std::os::unix::net::UnixListener, runtime: Arc<Runtime>) {
+    for stream in listener.incoming() {
+        let stream = stream.and_then(|stream| Ok((stream.try_clone()?, stream)));
+        match stream {
+            Ok((reader, writer)) => spawn_connection(reader, writer, runtime.clone()),
+            Err(e) => eprintln!("could not accept connection: {e}"),
+        }
+    }
+}
+
+fn spawn_connection<R, W>(reader: R, writer: W, runtime: Arc<Runtime>)
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    spawn(move || {
+        if let Err(e) = serve_connection(reader, writer, &runtime) {
+            eprintln!("connection failed: {e}");
+        }
+    });
+}
+
+/// A response to a line, in the order of the lines.
+enum Response {
+    /// The transaction was submitted, and its outcome will be received here
+    Pending(Receiver<Result<(), TransactionError>>),
+    /// The response to a query, including the line breaks
+    Ready(String),
+    Invalid(String),
+}
+
+/// Serve a single connection until the client closes it.
+///
+/// Lines are read and submitted on the calling thread without waiting for their outcome, while the
+/// responses are written on a separate thread in the order of the lines. This way a client can
+/// stream transactions without waiting for the response to every line.
+pub fn serve_connection<R, W>(reader: R, writer: W, runtime: &Runtime) -> std::io::Result<()>
+where
+    R: Read,
+    W: Write + Send + 'static,
+{
+    let (responses, pending) = channel();
+    let writer = spawn(move || write_responses(writer, pending));
+    let mut result = Ok(());
+    let mut lines = csv_line_reader(reader);
+    let mut line = csv::StringRecord::new();
+    loop {
+        let response = match lines.read_record(&mut line) {
+            Ok(true) => respond(&line, runtime),
+            Ok(false) => break,
+            Err(e) if e.is_io_error() => {
+                result = Err(e.into());
+                break;
+            }
+            Err(e) => Response::Invalid(e.to_string()),
+        };
+        if responses.send(response).is_err() {
+            // the writer failed, and reports why below
+            break;
+        }
+    }
+    drop(responses);
+    let written = writer.join().expect("Response writer panicked"); // this would be a bug
+    result.and(written)
+}
+
+/// Submit the transaction on `line`, or answer the query on it.
+fn respond(line: &csv::StringRecord, runtime: &Runtime) -> Response {
+    match line.get(0) {
+        Some("balance") => match line.get(1).map(str::parse::<ClientId>) {
+            Some(Ok(client_id)) => {
+                let account = runtime.call(&client_id, move |shard| {
+                    let account = shard.accounts.get(client_id)?;
+                    Some(AccountRecord::new(client_id, account))
+                });
+                Response::Ready(match account {
+                    Ok(Some(account)) => format!("balance,{}\n", account_line(&account)),
+                    Ok(None) => "error,client_not_found\n".to_string(),
+                    Err(_) => "error,internal\n".to_string(),
+                })
+            }
+            _ => Response::Invalid("expected balance,<client>".to_string()),
+        },
+        Some("accounts") => {
+            let shards = runtime.snapshot(|shard| {
+                shard
+                    .accounts
+                    .iter()
+                    .map(|(client_id, account)| AccountRecord::new(client_id, account))
+                    .collect::<Vec<_>>()
+            });
+            let Ok(shards) = shards.into_iter().collect::<Result<Vec<_>, _>>() else {
+                return Response::Ready("error,internal\n".to_string());
+            };
+            let mut accounts = shards.into_iter().flatten().collect::<Vec<_>>();
+            accounts.sort_unstable_by_key(|account| account.client);
+            let mut response = format!("accounts,{}\n", accounts.len());
+            for account in &accounts {
+                // writing to a string can't fail
+                let _ = writeln!(response, "{}", account_line(account));
+            }
+            Response::Ready(response)
+        }
+        Some("stats") => {
+            let stats = runtime.stats();
+            let mut response = format!("stats,{}\n", stats.len());
+            for (shard, stats) in stats.iter().enumerate() {
+                let quantile = |q| stats.latency.quantile(q).unwrap_or_default().as_nanos();
+                let _ = writeln!(
+                    response,
+                    "{shard},{},{},{},{},{},{},{}",
+                    stats.items,
+                    stats.queue_depth,
+                    stats.queue_high_water_mark,
+                    stats.busy.as_nanos(),
+                    stats.idle.as_nanos(),
+                    quantile(0.5),
+                    quantile(0.99),
+                );
+            }
+            Response::Ready(response)
+        }
+        // like a csv input, a transaction may leave out its amount, but has no more columns
+        _ if line.len() > 4 => {
+            Response::Invalid(format!("{} columns, expected at most 4", line.len()))
+        }
+        _ => match line.deserialize::<CsvTransaction>(None) {
+            Ok(tx) => {
+                let (reply, outcome) = channel();
+                runtime.process_item(Request { tx, reply });
+                Response::Pending(outcome)
+            }
+            Err(e) => Response::Invalid(e.to_string()),
+        },
+    }
+}
+
+fn account_line(account: &AccountRecord) -> String {
+    format!(
+        "{},{},{},{},{}",
+        account.client, account.available, account.held, account.total, account.locked
+    )
+}
+
+/// Write the responses, flushing whenever there are no more responses to write right away.
+fn write_responses(writer: impl Write, responses: Receiver<Response>) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    let mut next = responses.recv().ok();
+    while let Some(response) = next {
+        match response {
+            // the shard always replies, unless processing the transaction panicked
+            Response::Pending(outcome) => match outcome.recv() {
+                Ok(Ok(())) => writeln!(writer, "ok")?,
+                Ok(Err(e)) => writeln!(writer, "error,{}", e.code())?,
+                Err(_) => writeln!(writer, "error,internal")?,
+            },
+            Response::Ready(response) => writer.write_all(response.as_bytes())?,
+            Response::Invalid(message) => writeln!(writer, "invalid,{message}")?,
+        }
+        next = match responses.try_recv() {
+            Ok(response) => Some(response),
+            Err(TryRecvError::Empty) => {
+                writer.flush()?;
+                responses.recv().ok()
+            }
+            Err(TryRecvError::Disconnected) => None,
+        };
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::assign::Modulo;
+    use std::io::{BufRead, BufReader};
+    use std::net::{Shutdown, TcpStream};
+
+    /// Send `lines` over a new connection and return the response lines.
+    fn send(addr: std::net::SocketAddr, lines: &str) -> Vec<String> {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(lines.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        BufReader::new(stream).lines().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_serve_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let runtime = runtime(2, Box::new(Modulo), false).unwrap();
+        spawn(move || serve_tcp(listener, runtime));
+
+        let responses = send(
+            addr,
+            "deposit, 1, 1, 5.0\n\
+            withdrawal, 1, 2, 10.0\n\
+            refund, 1, 3\n\
+            deposit, 2, 4, 1.0\n",
+        );
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[0], "ok");
+        assert_eq!(responses[1], "error,insufficient_funds");
+        assert!(responses[2].starts_with("invalid,"));
+        assert_eq!(responses[3], "ok");
+
+        // the accounts outlive the connection
+        let responses = send(
+            addr,
+            "dispute, 1, 1\nwithdrawal, 1, 5, 1.0\ndeposit, 1, 6, 1.0, 1\n",
+        );
+        assert_eq!(responses[..2], ["ok", "error,insufficient_funds"]);
+        assert!(responses[2].starts_with("invalid,"));
+    }
+
+    #[test]
+    fn test_queries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let runtime = runtime(2, Box::new(Modulo), false).unwrap();
+        spawn(move || serve_tcp(listener, runtime));
+
+        let responses = send(
+            addr,
+            "deposit, 2, 1, 5.0\n\
+            deposit, 1, 2, 1.5\n\
+            dispute, 2, 1\n\
+            balance, 2\n\
+            balance, 3\n\
+            balance\n\
+            accounts\n",
+        );
+        assert_eq!(responses.len(), 9);
+        assert_eq!(responses[3], "balance,2,0,5,5,false");
+        assert_eq!(responses[4], "error,client_not_found");
+        assert!(responses[5].starts_with("invalid,"));
+        assert_eq!(
+            responses[6..],
+            ["accounts,2", "1,1.5,0,1.5,false", "2,0,5,5,false"]
+        );
+
+        let responses = send(addr, "stats\n");
+        let shards: usize = responses[0]
+            .strip_prefix("stats,")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(responses.len(), shards + 1);
+        let items = responses[1..]
+            .iter()
+            .map(|line| line.split(',').nth(1).unwrap().parse::<u64>().unwrap())
+            .sum::<u64>();
+        assert_eq!(items, 3);
+    }
+
+    #[test]
+    fn test_gauges() {
+        let mut from = Shard::default();
+        let mut to = Shard::default();
+        for (tx_type, client, tx, amount) in [
+            (CsvTransactionType::Deposit, 1, 1, Some(5.0)),
+            (CsvTransactionType::Deposit, 2, 2, Some(2.0)),
+            (CsvTransactionType::Dispute, 1, 1, None),
+            (CsvTransactionType::Dispute, 2, 2, None),
+            (CsvTransactionType::Chargeback, 2, 2, None),
+        ] {
+            let tx = CsvTransaction::new(tx_type, client, tx, amount);
+            apply(
+                &mut from,
+                Request {
+                    tx,
+                    reply: channel().0,
+                },
+            );
+        }
+        assert_eq!(from.counters.accounts, 2);
+        assert_eq!(from.counters.locked_accounts, 1);
+        assert_eq!(from.counters.held_funds, 5.0);
+
+        let account = from.take(1).unwrap();
+        to.put(1, account);
+        assert_eq!(from.counters.accounts, 1);
+        assert_eq!(from.counters.held_funds, 0.0);
+        assert_eq!(to.counters.accounts, 1);
+        assert_eq!(to.counters.held_funds, 5.0);
+    }
+}
@@ -0,0 +1,259 @@
+//! An HTTP/JSON API over the same runtime as the socket server.
+//!
+//! - `POST /transactions` submits a transaction object with the fields of the csv format, e.g.
+//!   `{"type": "deposit", "client": 1, "tx": 1, "amount": 1.0}`, or an array of them. A single
+//!   transaction is answered with `{"status": "ok"}`, or with status 422 and
+//!   `{"status": "rejected", "error": "<code>"}`, where `<code>` is a `TransactionError::code`.
+//!   A transaction whose processing panicked is quarantined, see `crate::rt::outcome`, and
+//!   answered with status 500 and `{"status": "failed"}`. A batch is answered with an array
+//!   holding the outcome of every transaction, in order.
+//! - `GET /accounts/{client}` returns the account of a client, or status 404 if it has none.
+//! - `GET /accounts` returns all accounts ordered by client id, see `Runtime::snapshot`.
+//!
+//! Accounts are answered with status 500 and the error `shard_failed` if a shard holding them
+//! failed, see `crate::rt::outcome`. They have the fields of the csv output format. Other errors
+//! are answered with the fitting status and `{"error": "<code>", "message": "<details>"}`.
+
+use super::{Request, Runtime};
+use crate::account::{ClientId, TransactionError};
+use crate::io::{AccountRecord, CsvTransaction};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::mpsc::{RecvError, channel};
+use std::thread::spawn;
+use tiny_http::{Header, Method, Response, Server};
+
+/// The number of threads handling HTTP requests. Handlers mostly wait for the shards, so a few are
+/// enough to keep them busy.
+const HANDLER_THREADS: usize = 4;
+
+/// The body of `POST /transactions`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Submission {
+    Single(CsvTransaction),
+    Batch(Vec<CsvTransaction>),
+}
+
+/// The outcome of a submitted transaction
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum Outcome {
+    Ok,
+    Rejected { error: &'static str },
+    Failed,
+}
+
+impl From<Result<Result<(), TransactionError>, RecvError>> for Outcome {
+    /// The shard drops the reply channel without replying if processing the transaction panicked.
+    fn from(reply: Result<Result<(), TransactionError>, RecvError>) -> Self {
+        match reply {
+            Ok(Ok(())) => Outcome::Ok,
+            Ok(Err(e)) => Outcome::Rejected { error: e.code() },
+            Err(RecvError) => Outcome::Failed,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+/// Handle HTTP requests forever, on the calling thread and `HANDLER_THREADS - 1` more threads.
+pub fn serve_http(server: Server, runtime: Arc<Runtime>) {
+    let server = Arc::new(server);
+    for _ in 1..HANDLER_THREADS {
+        let server = server.clone();
+        let runtime = runtime.clone();
+        spawn(move || handle_requests(&server, &runtime));
+    }
+    handle_requests(&server, &runtime)
+}
+
+fn handle_requests(server: &Server, runtime: &Runtime) -> ! {
+    loop {
+        match server.recv() {
+            Ok(request) => handle(request, runtime),
+            Err(e) => eprintln!("could not receive http request: {e}"),
+        }
+    }
+}
+
+fn handle(mut request: tiny_http::Request, runtime: &Runtime) {
+    let (status, body) = route(&mut request, runtime);
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        eprintln!("could not send http response: {e}");
+    }
+}
+
+/// Answer a request with a status code and a json body.
+fn route(request: &mut tiny_http::Request, runtime: &Runtime) -> (u16, String) {
+    let path = request.url().split('?').next().unwrap_or_default();
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    match (request.method(), segments.as_slice()) {
+        (Method::Post, ["transactions"]) => match serde_json::from_reader(request.as_reader()) {
+            Ok(submission) => submit(submission, runtime),
+            Err(e) => error(400, "invalid_request", e.to_string()),
+        },
+        (Method::Get, ["accounts", client]) => match client.parse::<ClientId>() {
+            Ok(client_id) => {
+                let account = runtime.call(&client_id, move |shard| {
+                    let account = shard.accounts.get(client_id)?;
+                    Some(AccountRecord::new(client_id, account))
+                });
+                match account {
+                    Ok(Some(account)) => (200, json(&account)),
+                    Ok(None) => error(404, "client_not_found", format!("no account for {client}")),
+                    Err(e) => error(500, "shard_failed", e.to_string()),
+                }
+            }
+            Err(e) => error(400, "invalid_client", format!("{client}: {e}")),
+        },
+        (Method::Get, ["accounts"]) => {
+            let shards = runtime.snapshot(|shard| {
+                shard
+                    .accounts
+                    .iter()
+                    .map(|(client_id, account)| AccountRecord::new(client_id, account))
+                    .collect::<Vec<_>>()
+            });
+            let shards = match shards.into_iter().collect::<Result<Vec<_>, _>>() {
+                Ok(shards) => shards,
+                Err(e) => return error(500, "shard_failed", e.to_string()),
+            };
+            let mut accounts = shards.into_iter().flatten().collect::<Vec<_>>();
+            accounts.sort_unstable_by_key(|account| account.client);
+            (200, json(&accounts))
+        }
+        (_, ["transactions"] | ["accounts"] | ["accounts", _]) => {
+            let method = request.method();
+            error(405, "method_not_allowed", format!("{method} {path}"))
+        }
+        _ => error(404, "not_found", path.to_string()),
+    }
+}
+
+/// Submit transactions and wait for their outcome. The transactions of a batch are all submitted
+/// before waiting for any outcome, and are applied in order per client.
+fn submit(submission: Submission, runtime: &Runtime) -> (u16, String) {
+    let (transactions, single) = match submission {
+        Submission::Single(tx) => (vec![tx], true),
+        Submission::Batch(transactions) => (transactions, false),
+    };
+    let pending = transactions
+        .into_iter()
+        .map(|tx| {
+            let (reply, outcome) = channel();
+            runtime.process_item(Request { tx, reply });
+            outcome
+        })
+        .collect::<Vec<_>>();
+    let outcomes = pending
+        .into_iter()
+        .map(|outcome| Outcome::from(outcome.recv()))
+        .collect::<Vec<_>>();
+    match (single, outcomes.as_slice()) {
+        (true, [Outcome::Ok]) => (200, json(&outcomes[0])),
+        (true, [Outcome::Failed]) => (500, json(&outcomes[0])),
+        (true, [outcome]) => (422, json(outcome)),
+        _ => (200, json(&outcomes)),
+    }
+}
+
+fn error(status: u16, error: &'static str, message: String) -> (u16, String) {
+    (status, json(&ErrorBody { error, message }))
+}
+
+fn json(value: &impl Serialize) -> String {
+    // the values are plain structs, which always serialize
+    serde_json::to_string(value).expect("Could not serialize response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::assign::Modulo;
+    use crate::server::runtime;
+    use serde_json::{Value, json};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+
+    /// Send a request with a minimal HTTP client, and return the status and json body.
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+            Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn test_http_api() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let runtime = runtime(2, Box::new(Modulo), false).unwrap();
+        spawn(move || serve_http(server, runtime));
+
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 5.0}"#;
+        assert_eq!(
+            request(addr, "POST", "/transactions", deposit),
+            (200, json!({"status": "ok"}))
+        );
+        assert_eq!(
+            request(addr, "POST", "/transactions", deposit),
+            (
+                422,
+                json!({"status": "rejected", "error": "duplicate_transaction"})
+            )
+        );
+        let batch = r#"[
+            {"type": "deposit", "client": 2, "tx": 2, "amount": 2.5},
+            {"type": "withdrawal", "client": 2, "tx": 3, "amount": 3.0},
+            {"type": "dispute", "client": 1, "tx": 1}
+        ]"#;
+        assert_eq!(
+            request(addr, "POST", "/transactions", batch),
+            (
+                200,
+                json!([
+                    {"status": "ok"},
+                    {"status": "rejected", "error": "insufficient_funds"},
+                    {"status": "ok"}
+                ])
+            )
+        );
+        let (status, body) = request(addr, "POST", "/transactions", r#"{"type": "refund"}"#);
+        assert_eq!((status, &body["error"]), (400, &json!("invalid_request")));
+
+        let account_1 =
+            json!({"client": 1, "available": 0.0, "held": 5.0, "total": 5.0, "locked": false});
+        let account_2 =
+            json!({"client": 2, "available": 2.5, "held": 0.0, "total": 2.5, "locked": false});
+        assert_eq!(
+            request(addr, "GET", "/accounts/1", ""),
+            (200, account_1.clone())
+        );
+        assert_eq!(
+            request(addr, "GET", "/accounts", ""),
+            (200, json!([account_1, account_2]))
+        );
+        assert_eq!(request(addr, "GET", "/accounts/3", "").0, 404);
+        assert_eq!(request(addr, "GET", "/accounts/x", "").0, 400);
+        assert_eq!(request(addr, "DELETE", "/accounts", "").0, 405);
+        assert_eq!(request(addr, "GET", "/", "").0, 404);
+    }
+}
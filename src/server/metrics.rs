@@ -0,0 +1,226 @@
+//! Metrics of the server in the Prometheus text exposition format, served over plain HTTP on
+//! `GET /metrics`.
+//!
+//! Business metrics are the `Counters` of every shard, copied with a
+//! `ShardedThreadPerCoreRuntime::snapshot`, so a scrape is queued behind the transactions already
+//! submitted, but doesn't hold up the shards any longer than a copy of their counters. Failed
+//! shards are left out. Runtime metrics are read from the atomic counters of `crate::rt::metrics`,
+//! without involving the shards.
+
+use super::{Counters, Runtime};
+use crate::account::TransactionError;
+use crate::io::CsvTransactionType;
+use crate::rt::metrics::ShardStats;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a scrape may take to send its request, so a stalled client can't hold up the others.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answer scrapes forever, one connection at a time.
+pub fn serve_metrics(listener: TcpListener, runtime: Arc<Runtime>) {
+    for stream in listener.incoming() {
+        let stream = stream.and_then(|stream| {
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+            Ok(stream)
+        });
+        if let Err(e) = stream.and_then(|stream| respond(stream, &runtime)) {
+            eprintln!("could not serve metrics: {e}");
+        }
+    }
+}
+
+fn respond(stream: TcpStream, runtime: &Runtime) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip the headers, which don't matter for a scrape
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let (status, body) = match request_line.split(' ').nth(1) {
+        Some("/metrics") => ("200 OK", render(runtime)),
+        _ => (
+            "404 Not Found",
+            "not found, metrics are served on /metrics\n".to_string(),
+        ),
+    };
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+        Content-Type: text/plain; version=0.0.4\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+/// Render all metrics in the Prometheus text format.
+pub fn render(runtime: &Runtime) -> String {
+    let mut all = Counters::default();
+    // a failed shard lost its counters along with its accounts
+    for counters in runtime
+        .snapshot(|shard| shard.counters.clone())
+        .into_iter()
+        .flatten()
+    {
+        all.merge(&counters);
+    }
+
+    // writing to a string can't fail
+    let mut out = String::new();
+    family(
+        &mut out,
+        "ktht_transactions_total",
+        "counter",
+        "Transactions submitted, by type",
+    );
+    for tx_type in CsvTransactionType::ALL {
+        let count = all.transactions[tx_type as usize];
+        let _ = writeln!(
+            out,
+            "ktht_transactions_total{{type=\"{}\"}} {count}",
+            tx_type.as_str()
+        );
+    }
+    family(
+        &mut out,
+        "ktht_rejections_total",
+        "counter",
+        "Transactions rejected, by error",
+    );
+    for error in TransactionError::ALL {
+        let count = all.rejections[error as usize];
+        let _ = writeln!(
+            out,
+            "ktht_rejections_total{{error=\"{}\"}} {count}",
+            error.code()
+        );
+    }
+    family(&mut out, "ktht_accounts", "gauge", "Client accounts");
+    let _ = writeln!(out, "ktht_accounts {}", all.accounts);
+    family(
+        &mut out,
+        "ktht_locked_accounts",
+        "gauge",
+        "Accounts locked by a chargeback",
+    );
+    let _ = writeln!(out, "ktht_locked_accounts {}", all.locked_accounts);
+    family(
+        &mut out,
+        "ktht_held_funds",
+        "gauge",
+        "Funds held by disputes, over all accounts",
+    );
+    let _ = writeln!(out, "ktht_held_funds {}", all.held_funds);
+
+    let stats = runtime.stats();
+    let shard_family =
+        |out: &mut String, name, metric_type, help, value: fn(&ShardStats) -> f64| {
+            family(out, name, metric_type, help);
+            for (shard, stats) in stats.iter().enumerate() {
+                let _ = writeln!(out, "{name}{{shard=\"{shard}\"}} {}", value(stats));
+            }
+        };
+    shard_family(
+        &mut out,
+        "ktht_shard_items_total",
+        "counter",
+        "Items processed by a shard",
+        |stats| stats.items as f64,
+    );
+    shard_family(
+        &mut out,
+        "ktht_shard_panics_total",
+        "counter",
+        "Items whose processing panicked on a shard",
+        |stats| stats.panics as f64,
+    );
+    shard_family(
+        &mut out,
+        "ktht_shard_queue_depth",
+        "gauge",
+        "Messages waiting for a shard",
+        |stats| stats.queue_depth as f64,
+    );
+    shard_family(
+        &mut out,
+        "ktht_shard_queue_high_water_mark",
+        "gauge",
+        "Most messages that waited for a shard at once",
+        |stats| stats.queue_high_water_mark as f64,
+    );
+    shard_family(
+        &mut out,
+        "ktht_shard_busy_seconds_total",
+        "counter",
+        "Time a shard spent processing",
+        |stats| stats.busy.as_secs_f64(),
+    );
+    shard_family(
+        &mut out,
+        "ktht_shard_idle_seconds_total",
+        "counter",
+        "Time a shard spent waiting",
+        |stats| stats.idle.as_secs_f64(),
+    );
+    out
+}
+
+fn family(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {metric_type}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::assign::Modulo;
+    use crate::server::{runtime, serve_connection};
+    use std::io::Read;
+    use std::thread::spawn;
+
+    #[test]
+    fn test_metrics() {
+        let runtime = runtime(2, Box::new(Modulo), false).unwrap();
+        let transactions = "deposit, 1, 1, 5.0\n\
+            deposit, 2, 2, 1.0\n\
+            withdrawal, 2, 3, 2.0\n\
+            dispute, 1, 1\n\
+            chargeback, 1, 1\n\
+            deposit, 1, 4, 1.0\n\
+            dispute, 2, 2\n";
+        serve_connection(transactions.as_bytes(), std::io::sink(), &runtime).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn(move || serve_metrics(listener, runtime));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        for line in [
+            "ktht_transactions_total{type=\"deposit\"} 3",
+            "ktht_transactions_total{type=\"chargeback\"} 1",
+            "ktht_transactions_total{type=\"dispute\"} 2",
+            "ktht_rejections_total{error=\"insufficient_funds\"} 1",
+            "ktht_rejections_total{error=\"account_locked\"} 1",
+            "ktht_accounts 2",
+            "ktht_locked_accounts 1",
+            "ktht_held_funds 1",
+            "ktht_shard_queue_depth{shard=\"0\"} 0",
+        ] {
+            assert!(response.lines().any(|l| l == line), "{line} in {response}");
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! End-to-end tests of the command line, running the binary on inputs written to a temporary
+//! directory. The outputs of successful runs are covered by the golden tests.
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use ktht::cli::EXIT_FAILURE;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// A new empty directory for the files of test `name`.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ktht-cli-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn ktht(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ktht"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_failed_run_leaves_no_output() {
+    let dir = temp_dir("failed_run");
+    let missing = dir.join("missing.csv");
+    let output = dir.join("accounts.csv");
+    std::fs::write(&output, "previous accounts\n").unwrap();
+
+    let run = ktht(&[
+        "process",
+        missing.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+    assert_eq!(run.status.code(), Some(i32::from(EXIT_FAILURE)));
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "previous accounts\n"
+    );
+
+    let run = ktht(&["process", missing.to_str().unwrap()]);
+    assert_eq!(run.status.code(), Some(i32::from(EXIT_FAILURE)));
+    assert!(run.stdout.is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_process_without_command() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/golden/example/input.csv"
+    );
+    let process = ktht(&["process", input, "--sort"]);
+    let bare = ktht(&[input, "--sort"]);
+    assert!(bare.status.success());
+    assert_eq!(
+        String::from_utf8(bare.stdout).unwrap(),
+        String::from_utf8(process.stdout).unwrap()
+    );
+}
+
+#[test]
+fn test_unreadable_input_is_not_a_bad_row() {
+    let dir = temp_dir("unreadable_input");
+    let mut csv = "type,client,tx,amount\n".to_string();
+    for tx in 1..=2000 {
+        csv += &format!("deposit,{},{tx},1.0\n", tx % 10);
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(csv.as_bytes()).unwrap();
+    let gz = encoder.finish().unwrap();
+    let truncated = dir.join("truncated.csv.gz");
+    std::fs::write(&truncated, &gz[..gz.len() / 2]).unwrap();
+    let missing = dir.join("missing.csv");
+
+    for input in [truncated, missing] {
+        let run = ktht(&[
+            "process",
+            input.to_str().unwrap(),
+            "--max-bad-rows",
+            "1000000",
+        ]);
+        assert_eq!(run.status.code(), Some(i32::from(EXIT_FAILURE)));
+        assert!(run.stdout.is_empty());
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_placement() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/golden/example/input.csv"
+    );
+    let run = ktht(&["process", input]);
+    assert!(run.status.success());
+    assert!(run.stderr.is_empty());
+    let run = ktht(&["process", input, "--verbose"]);
+    assert!(
+        String::from_utf8(run.stderr)
+            .unwrap()
+            .starts_with("running ")
+    );
+
+    let run = ktht(&["process", input, "--cores", "0", "--reader-core", "0"]);
+    assert_eq!(run.status.code(), Some(i32::from(EXIT_FAILURE)));
+    assert!(run.stdout.is_empty());
+}
@@ -0,0 +1,34 @@
+//! Runs the seed corpus of the csv fuzz targets through the same checks, see
+//! `support::csv_accounts`.
+
+mod support;
+
+use std::path::Path;
+use support::csv_accounts;
+
+/// The contents of the seeds of a fuzz target, which are checked in with the fuzz targets.
+fn seeds(target: &str) -> Vec<Vec<u8>> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz/seeds")
+        .join(target);
+    let seeds = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+        .collect::<Vec<_>>();
+    assert!(!seeds.is_empty(), "No seeds for {target}");
+    seeds
+}
+
+#[test]
+fn test_csv_transactions_seeds() {
+    for seed in seeds("csv_transactions") {
+        csv_accounts::check_csv_transactions(&seed);
+    }
+}
+
+#[test]
+fn test_csv_lines_seeds() {
+    for seed in seeds("csv_lines") {
+        csv_accounts::check_csv_lines(&seed);
+    }
+}
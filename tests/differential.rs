@@ -0,0 +1,46 @@
+//! Checks that the sharded runtimes end up with the same accounts as applying the transactions
+//! sequentially, on scenarios generated from a fixed set of seeds, see `support::differential`.
+//! The same check runs as the `differential` fuzz target.
+
+mod support;
+
+use arbitrary::{Arbitrary, Unstructured};
+use support::differential::{self, Op, Scenario};
+
+/// The number of scenarios checked
+const SEEDS: u64 = 64;
+/// The number of bytes every scenario is generated from, enough for several hundred ops
+const SCENARIO_BYTES: usize = 4096;
+
+/// Bytes from a xorshift generator, so every run checks the same scenarios.
+fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    let mut bytes = Vec::with_capacity(len + 8);
+    while bytes.len() < len {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        bytes.extend_from_slice(&state.to_le_bytes());
+    }
+    bytes.truncate(len);
+    bytes
+}
+
+#[test]
+fn test_sharded_equals_sequential() {
+    for seed in 0..SEEDS {
+        let bytes = random_bytes(seed, SCENARIO_BYTES);
+        let mut u = Unstructured::new(&bytes);
+        let mut ops = Vec::new();
+        while !u.is_empty() {
+            ops.push(Op::arbitrary(&mut u).unwrap());
+        }
+        for shards in 0..8 {
+            differential::check(&Scenario {
+                shards,
+                hash: seed % 2 == 1,
+                ops: ops.clone(),
+            });
+        }
+    }
+}
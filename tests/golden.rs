@@ -0,0 +1,114 @@
+//! End-to-end tests of the `process` command, running the binary on the scenarios in
+//! `tests/golden`, each a directory with:
+//!
+//! - `input.csv`, the transactions
+//! - `accounts.csv`, the expected accounts
+//! - `rejections.csv`, the expected rejection report, see `--errors`
+//! - `args`, optionally, further arguments of the command, separated by whitespace
+//! - `status`, optionally, the expected exit code, `cli::EXIT_SUCCESS` without one
+//!
+//! Every scenario runs with several thread counts, and on the deterministic runtime, and must
+//! produce the same output every time. The rows of the outputs are compared in sorted order, as
+//! the rejections of different shards are reported in no particular order. Run with
+//! `GOLDEN_UPDATE=1` to write the outputs of a single threaded run as the expected files.
+
+use ktht::cli::EXIT_SUCCESS;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The thread counts every scenario runs with, capped at the number of cores by the command
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+fn scenarios() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut scenarios = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    scenarios.sort();
+    assert!(!scenarios.is_empty(), "No golden scenarios");
+    scenarios
+}
+
+/// The contents of a csv output, with its header first and its rows sorted, and without carriage
+/// returns and trailing whitespace.
+fn normalize(output: &str) -> String {
+    let mut lines = output.lines().map(str::trim_end);
+    let header = lines.next().unwrap_or_default();
+    let mut rows = lines.filter(|line| !line.is_empty()).collect::<Vec<_>>();
+    rows.sort_unstable();
+    let mut normalized = header.to_string();
+    for row in rows {
+        normalized.push('\n');
+        normalized.push_str(row);
+    }
+    normalized.push('\n');
+    normalized
+}
+
+/// The normalized accounts and rejection report of processing the input of `scenario` with
+/// `runtime_args`, after checking the exit code.
+fn run(scenario: &Path, runtime_args: &[String]) -> (String, String) {
+    let name = scenario.file_name().unwrap().to_string_lossy();
+    let report = std::env::temp_dir().join(format!(
+        "ktht-golden-{}-{name}-{}.csv",
+        std::process::id(),
+        runtime_args.join("")
+    ));
+    let args = std::fs::read_to_string(scenario.join("args")).unwrap_or_default();
+    let output = Command::new(env!("CARGO_BIN_EXE_ktht"))
+        .arg("process")
+        .arg(scenario.join("input.csv"))
+        .arg("--errors")
+        .arg(&report)
+        .args(args.split_whitespace())
+        .args(runtime_args)
+        .output()
+        .unwrap();
+    let status = std::fs::read_to_string(scenario.join("status"))
+        .map_or(EXIT_SUCCESS, |status| status.trim().parse().unwrap());
+    assert_eq!(
+        output.status.code(),
+        Some(i32::from(status)),
+        "{name} {runtime_args:?}: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let rejections = std::fs::read_to_string(&report).unwrap();
+    std::fs::remove_file(&report).unwrap();
+    (
+        normalize(&String::from_utf8(output.stdout).unwrap()),
+        normalize(&rejections),
+    )
+}
+
+#[test]
+fn test_golden() {
+    let update = std::env::var_os("GOLDEN_UPDATE").is_some();
+    for scenario in scenarios() {
+        let accounts_path = scenario.join("accounts.csv");
+        let rejections_path = scenario.join("rejections.csv");
+        if update {
+            let (accounts, rejections) = run(&scenario, &["-j1".to_string()]);
+            std::fs::write(&accounts_path, accounts).unwrap();
+            std::fs::write(&rejections_path, rejections).unwrap();
+        }
+        let accounts = normalize(&std::fs::read_to_string(&accounts_path).unwrap());
+        let rejections = normalize(&std::fs::read_to_string(&rejections_path).unwrap());
+        let runs = THREADS
+            .iter()
+            .map(|threads| vec![format!("-j{threads}")])
+            .chain([vec!["-j4".to_string(), "--deterministic".to_string()]]);
+        let name = scenario.file_name().unwrap().to_string_lossy();
+        for runtime_args in runs {
+            let (actual_accounts, actual_rejections) = run(&scenario, &runtime_args);
+            assert_eq!(
+                actual_accounts, accounts,
+                "{name} {runtime_args:?}: accounts"
+            );
+            assert_eq!(
+                actual_rejections, rejections,
+                "{name} {runtime_args:?}: rejections"
+            );
+        }
+    }
+}
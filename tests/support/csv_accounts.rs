@@ -0,0 +1,40 @@
+//! Feeding arbitrary bytes through the csv readers into `Accounts`, shared by the `csv_seeds` test
+//! and the `csv_transactions` and `csv_lines` fuzz targets.
+//!
+//! Rows that can't be read and transactions that are rejected are skipped, like the process
+//! command and the server do. Whatever the input, reading and applying it must not panic, and
+//! every balance must stay finite.
+
+use ktht::account::Accounts;
+use ktht::cmd::process_transaction;
+use ktht::io::{CsvTransaction, csv_line_reader, csv_transaction_reader};
+
+/// Apply a csv file with a header, as read by the process command.
+pub fn check_csv_transactions(data: &[u8]) {
+    let mut accounts = Accounts::default();
+    for tx in csv_transaction_reader(data).flatten() {
+        process_transaction(&mut accounts, tx);
+    }
+    check_balances(&accounts);
+}
+
+/// Apply csv lines without a header, as streamed to the server.
+pub fn check_csv_lines(data: &[u8]) {
+    let mut accounts = Accounts::default();
+    for record in csv_line_reader(data).records().flatten() {
+        if let Ok(tx) = record.deserialize::<CsvTransaction>(None) {
+            process_transaction(&mut accounts, tx);
+        }
+    }
+    check_balances(&accounts);
+}
+
+fn check_balances(accounts: &Accounts) {
+    for (client, account) in accounts.iter() {
+        let funds = [account.available(), account.held(), account.total()];
+        assert!(
+            funds.iter().all(|funds| funds.is_finite()),
+            "Client {client} has non-finite funds {account:?}"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! A differential check of the sharded runtimes against applying the same transactions
+//! sequentially to one `Accounts`, shared by the `differential` test and fuzz target.
+//!
+//! A `Scenario` is generated from arbitrary bytes. Its transactions use few clients and
+//! transaction ids, so disputes, resolves and chargebacks mostly refer to earlier deposits, and
+//! clients get locked along the way. Every client must end up with the same balances and lock
+//! state on every runtime, regardless of the number of shards and how clients are assigned or
+//! moved between them.
+
+use arbitrary::Arbitrary;
+use ktht::account::{Accounts, ClientId};
+use ktht::cmd::process_transaction;
+use ktht::io::{CsvTransaction, CsvTransactionType};
+use ktht::rt::affinity::CoreList;
+use ktht::rt::assign::{HashMix, Modulo, ShardAssigner};
+use ktht::rt::deterministic::DeterministicRuntime;
+use ktht::rt::{RuntimeError, ShardedThreadPerCoreRuntime};
+use std::collections::BTreeMap;
+use std::convert::identity;
+
+/// The number of distinct clients
+const CLIENTS: u8 = 8;
+/// The number of distinct transaction ids of every client
+const TX_IDS: u8 = 16;
+/// The most shards a scenario runs on
+const MAX_SHARDS: u8 = 8;
+
+/// A stream of transactions, and the runtime to apply them on
+#[derive(Arbitrary, Clone, Debug)]
+pub struct Scenario {
+    /// The number of shards, modulo `MAX_SHARDS`, plus one
+    pub shards: u8,
+    /// Whether clients are assigned to shards with `HashMix` instead of `Modulo`
+    pub hash: bool,
+    pub ops: Vec<Op>,
+}
+
+/// A transaction, or a client moved to another shard
+#[derive(Arbitrary, Clone, Copy, Debug)]
+pub enum Op {
+    Deposit {
+        client: u8,
+        tx: u8,
+        cents: u16,
+    },
+    Withdrawal {
+        client: u8,
+        tx: u8,
+        cents: u16,
+    },
+    Dispute {
+        client: u8,
+        tx: u8,
+    },
+    Resolve {
+        client: u8,
+        tx: u8,
+    },
+    Chargeback {
+        client: u8,
+        tx: u8,
+    },
+    /// Move the client to another shard of the threaded runtime, see
+    /// `ShardedThreadPerCoreRuntime::migrate`
+    Migrate {
+        client: u8,
+        shard: u8,
+    },
+}
+
+impl Op {
+    /// The transaction of the op, if it is one.
+    fn transaction(self) -> Option<CsvTransaction> {
+        let (tx_type, client, tx, cents) = match self {
+            Op::Deposit { client, tx, cents } => (CsvTransactionType::Deposit, client, tx, cents),
+            Op::Withdrawal { client, tx, cents } => {
+                (CsvTransactionType::Withdrawal, client, tx, cents)
+            }
+            Op::Dispute { client, tx } => (CsvTransactionType::Dispute, client, tx, 0),
+            Op::Resolve { client, tx } => (CsvTransactionType::Resolve, client, tx, 0),
+            Op::Chargeback { client, tx } => (CsvTransactionType::Chargeback, client, tx, 0),
+            Op::Migrate { .. } => return None,
+        };
+        let amount = match tx_type {
+            CsvTransactionType::Deposit | CsvTransactionType::Withdrawal => {
+                Some(f32::from(cents) / 100.0)
+            }
+            _ => None,
+        };
+        Some(CsvTransaction::new(
+            tx_type,
+            client_id(client),
+            u32::from(tx % TX_IDS),
+            amount,
+        ))
+    }
+}
+
+fn client_id(client: u8) -> ClientId {
+    ClientId::from(client % CLIENTS)
+}
+
+/// The available, held and total funds, and the lock state of every client
+type Balances = BTreeMap<ClientId, (f64, f64, f64, bool)>;
+
+/// The balances of all clients of the shards, which must not share any client.
+fn balances(shards: impl IntoIterator<Item = Accounts>) -> Balances {
+    let mut balances = Balances::new();
+    for (client, account) in shards.into_iter().flatten() {
+        let balance = (
+            account.available(),
+            account.held(),
+            account.total(),
+            account.is_locked(),
+        );
+        assert!(
+            balances.insert(client, balance).is_none(),
+            "Client {client} is on several shards"
+        );
+    }
+    balances
+}
+
+impl Scenario {
+    fn assigner(&self) -> Box<dyn ShardAssigner> {
+        if self.hash {
+            Box::new(HashMix)
+        } else {
+            Box::new(Modulo)
+        }
+    }
+
+    fn transactions(&self) -> impl Iterator<Item = CsvTransaction> + Clone + '_ {
+        self.ops.iter().filter_map(|op| op.transaction())
+    }
+}
+
+/// Apply the transactions of `scenario` sequentially, and on both runtimes, and assert that every
+/// client ends up with the same balances and lock state.
+pub fn check(scenario: &Scenario) {
+    let shards = usize::from(scenario.shards % MAX_SHARDS) + 1;
+    let mut accounts = Accounts::default();
+    for tx in scenario.transactions() {
+        process_transaction(&mut accounts, tx);
+    }
+    let expected = balances([accounts]);
+
+    let outcome = DeterministicRuntime::try_fold(
+        shards,
+        scenario.assigner(),
+        process_transaction,
+        identity,
+        scenario.transactions().map(Ok::<_, RuntimeError>),
+    )
+    .unwrap();
+    let actual = balances(outcome.states().unwrap());
+    assert_eq!(
+        actual, expected,
+        "Deterministic runtime with {shards} shards"
+    );
+
+    // the threaded runtime starts at most one shard per core
+    let threads = shards.min(CoreList::available().unwrap().ids().len());
+    let rt = ShardedThreadPerCoreRuntime::<CsvTransaction, _, Accounts>::new_migratable(
+        threads,
+        scenario.assigner(),
+        process_transaction,
+    )
+    .unwrap();
+    for &op in &scenario.ops {
+        if let Op::Migrate { client, shard } = op {
+            rt.migrate(client_id(client).into(), usize::from(shard) % threads);
+        } else if let Some(tx) = op.transaction() {
+            rt.process_item(tx);
+        }
+    }
+    let outcome = rt.finish();
+    assert!(outcome.quarantined.is_empty());
+    let actual = balances(outcome.states().unwrap());
+    assert_eq!(actual, expected, "Threaded runtime with {threads} shards");
+}
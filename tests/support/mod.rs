@@ -0,0 +1,7 @@
+//! Code shared by the integration tests.
+
+// every test uses only some of the support modules
+#![allow(dead_code)]
+
+pub mod csv_accounts;
+pub mod differential;